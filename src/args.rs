@@ -1,46 +1,182 @@
-use std::env;
+use std::{env, path};
 
-use hcs_lib::client_detect_offline;
+use crate::{
+    config, conflicts, control, detect, dry_run, engine, errors, history, lock, pins, placeholders,
+    rate_limit, remote, selective, status,
+};
 
-use crate::{config, sync_client_to_server, sync_server_to_client};
+fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|position| args.get(position + 1))
+        .map(String::as_str)
+}
+
+fn has_flag(args: &[String], flag: &str) -> bool {
+    args.iter().any(|arg| arg == flag)
+}
+
+fn required_path(
+    args: &[String],
+    index: usize,
+) -> Result<path::PathBuf, Box<dyn std::error::Error>> {
+    match args.get(index) {
+        Some(arg) if !arg.is_empty() && !arg.starts_with("--") => Ok(path::PathBuf::from(arg)),
+        _ => Err(format!("Usage: hcs {} <path>", args[1..index].join(" ")).into()),
+    }
+}
+
+fn touches_sync_state(args: &[String]) -> bool {
+    matches!(
+        (&*args[1], &*args[2]),
+        (
+            "detect" | "sync" | "sync-now" | "hydrate" | "open" | "dehydrate" | "pin" | "unpin",
+            _
+        ) | ("selective", "add" | "remove")
+            | ("conflicts", "resolve" | "diff")
+    )
+}
+
+// Waits while a running `hcs live` syncs, rather than failing until it is stopped.
+fn acquire_lock(
+    config: &config::ClientConfig,
+) -> Result<lock::InstanceLock, Box<dyn std::error::Error>> {
+    let err = match lock::InstanceLock::acquire(config) {
+        Ok(lock) => return Ok(lock),
+        Err(err) => err,
+    };
+    let holder = match err.downcast_ref() {
+        Some(errors::ClientError::AlreadyRunning(holder)) => *holder,
+        _ => return Err(err),
+    };
+    match control::daemon_status(config)? {
+        Some(daemon_status) if holder.is_none() || holder == Some(daemon_status.pid) => {
+            log::info!("Waiting for hcs live to finish syncing");
+            lock::InstanceLock::wait(config)
+        }
+        _ => Err(err),
+    }
+}
 
 pub fn run_from_args(config: &config::ClientConfig) -> Result<(), Box<dyn std::error::Error>> {
     let mut args: Vec<String> = env::args().collect();
     if args.len() == 2 {
         args.push("".to_string())
     }
+    let mut config = config.clone();
+    if let Some(rate) = flag_value(&args, "--limit-rate") {
+        config.override_rate_limit(rate_limit::parse_rate(rate)?);
+    }
+    let config = &config;
+
+    // A running `hcs live` owns the sync state, so it does the syncing and reports the status.
+    let live_status = match &*args[1] {
+        "sync" if has_flag(&args, "--dry-run") => None,
+        "sync" | "sync-now" | "status" => control::daemon_status(config)?,
+        _ => None,
+    };
+    // Anything else touching `ServerVersion`, `changes/` or the storage tree runs one at a time.
+    // `hcs live` takes the lock for each sync round instead.
+    let _lock = if live_status.is_none() && args[1] != "live" && touches_sync_state(&args) {
+        Some(acquire_lock(config)?)
+    } else {
+        None
+    };
+
     match (&*args[1], &*args[2]) {
-        ("detect", _) => {
-            client_detect_offline::detect_offline_changes(&config.file_handler_config())
+        ("detect", _) => detect(config)?,
+        ("sync", direction) if has_flag(&args, "--dry-run") => {
+            dry_run::dry_run(config, direction != "down", direction != "up")?;
+        }
+        ("sync", direction) | ("sync-now", direction) if live_status.is_some() => {
+            log::info!("hcs live is running, asking it to sync");
+            let direction = match direction {
+                "up" => control::SyncDirection::Up,
+                "down" => control::SyncDirection::Down,
+                _ => control::SyncDirection::Both,
+            };
+            control::command(config, control::Request::SyncNow(direction))?;
+        }
+        ("sync", "up") | ("sync-now", "up") => {
+            engine::block_on(engine::SyncEngine::new(config).sync_up())?
+        }
+        ("sync", "down") | ("sync-now", "down") => {
+            engine::block_on(engine::SyncEngine::new(config).sync_down())?
         }
-        ("sync", "up") => {
-            client_detect_offline::detect_offline_changes(&config.file_handler_config());
-            sync_client_to_server::sync_client_to_server(&config).unwrap();
+        ("sync", _) | ("sync-now", _) => engine::block_on(engine::SyncEngine::new(config).sync())?,
+        ("status", _) => status::status(config, has_flag(&args, "--json"), live_status)?,
+        ("pause", _) => {
+            control::command(config, control::Request::Pause)?;
+            println!("Live mode paused.");
         }
-        ("sync", "down") => {
-            client_detect_offline::detect_offline_changes(&config.file_handler_config());
-            sync_server_to_client::sync_server_to_client(&config).unwrap();
+        ("resume", _) => {
+            control::command(config, control::Request::Resume)?;
+            println!("Live mode resumed.");
         }
-        ("sync", _) => {
-            client_detect_offline::detect_offline_changes(&config.file_handler_config());
-            sync_server_to_client::sync_server_to_client(&config).unwrap();
-            sync_client_to_server::sync_client_to_server(&config).unwrap();
+        ("log", _) => history::log(
+            config,
+            flag_value(&args, "--path").map(path::Path::new),
+            flag_value(&args, "--since"),
+        )?,
+        ("ls-remote", remote_path) => remote::ls_remote(config, remote_path)?,
+        ("fetch", "") => return Err("Usage: hcs fetch <path> [-o dest]".into()),
+        ("fetch", remote_path) => {
+            let destination = flag_value(&args, "-o").map(path::Path::new);
+            remote::fetch(config, remote_path, destination)?;
         }
-        ("live", _) => {
-            // detect_offline_changes(&config);
-            // sync_client_to_server(&config);
-            // sync_server_to_client(&config);
-            // run_live(&config);
-            unimplemented!("Live mode is not yet implemented.")
+        ("selective", "list") | ("selective", "") => selective::list(config)?,
+        ("selective", "add") => selective::add(config, &required_path(&args, 3)?)?,
+        ("selective", "remove") => selective::remove(config, &required_path(&args, 3)?)?,
+        ("hydrate", _) => placeholders::hydrate(config, &required_path(&args, 2)?)?,
+        ("open", _) => {
+            let storage_path = placeholders::ensure_hydrated(config, &required_path(&args, 2)?)?;
+            println!("{}", storage_path.display());
         }
+        ("dehydrate", _) => placeholders::dehydrate(config, &required_path(&args, 2)?)?,
+        ("pin", _) => pins::pin(config, &required_path(&args, 2)?)?,
+        ("unpin", _) => pins::unpin(config, &required_path(&args, 2)?)?,
+        ("conflicts", "list") | ("conflicts", "") => conflicts::list_conflicts(config)?,
+        ("conflicts", "resolve") => {
+            let keep = flag_value(&args, "--keep")
+                .ok_or("Usage: hcs conflicts resolve <path> --keep local|remote|both")?
+                .parse()?;
+            conflicts::resolve_conflict(config, &required_path(&args, 3)?, keep)?;
+        }
+        ("conflicts", "diff") => conflicts::diff_conflict(config, &required_path(&args, 3)?)?,
+        ("live", _) => engine::block_on(engine::SyncEngine::new(config).run_live())?,
         ("help", _) => {
             println!(
                 "hcs detect\t- Detects any changes that were made while the program was offline."
             );
-            println!("hcs live\t- (UNIMPLEMENTED)\tDetects, then watches the `shortcut` directory for changes. Periodically syncs to and from server.");
-            println!("hcs sync up\t- Detects, then syncs local changes to the server.");
+            println!("hcs live\t- Syncs shortly after local files change and every `live_interval_secs`, until interrupted.");
+            println!("hcs sync up\t- Detects, then syncs local changes to the server. Syncs down first if the server is ahead.");
             println!("hcs sync down\t- Detects, then syncs server changes to client");
             println!("hcs sync\t- Detects, then syncs up, then syncs down");
+            println!("hcs sync [up|down] --dry-run\t- Prints what would be synced without changing anything. Local edits show up once `hcs detect` has recorded them.");
+            println!("hcs sync-now [up|down]\t- Asks the running `hcs live` to sync right away, or syncs like `hcs sync` if it is not running. `hcs sync` asks it too.");
+            println!("hcs status [--json]\t- Shows pending local changes and how far the server is ahead, and the state of `hcs live`, without changing anything. Local edits show up once `hcs detect` has recorded them.");
+            println!("hcs pause\t- Stops the running `hcs live` from syncing on its interval.");
+            println!("hcs resume\t- Lets the running `hcs live` sync on its interval again.");
+            println!("hcs log [--path P] [--since T]\t- Shows the changes applied by past syncs, e.g. --since 2023-05-07 or --since 12h.");
+            println!("hcs ls-remote [path]\t- Lists the server's tree.");
+            println!("hcs fetch <path> [-o dest]\t- Downloads a single file from the server without syncing.");
+            println!("hcs selective list\t- Lists the selective sync rules.");
+            println!(
+                "hcs selective add <dir>\t- Mirrors a directory locally again and downloads it."
+            );
+            println!("hcs selective remove <dir>\t- Stops mirroring a directory and frees its local copy.");
+            println!("hcs hydrate <path>\t- Downloads the contents of cloud-only files.");
+            println!("hcs open <path>\t- Downloads a cloud-only file if needed and prints where to open it.");
+            println!("hcs dehydrate <path>\t- Frees the local copy of synced files, leaving cloud-only placeholders.");
+            println!("hcs pin <path>\t- Always keeps a file or directory downloaded.");
+            println!("hcs unpin <path>\t- Allows a file or directory to be evicted again.");
+            println!("hcs conflicts list\t- Lists conflicts preserved during sync down.");
+            println!(
+                "hcs conflicts resolve <path> --keep local|remote|both\t- Resolves a conflict."
+            );
+            println!("hcs conflicts diff <path>\t- Shows the differences between both versions of a text file.");
+            println!();
+            println!("--limit-rate <rate>\t- Limits uploads and downloads to e.g. 500k or 2M bytes per second, overriding `[rate_limit]`.");
         }
         _ => (),
     }
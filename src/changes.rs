@@ -0,0 +1,294 @@
+use std::{collections::HashMap, fs, path};
+
+use hcs_lib::{client_database, data};
+
+pub fn change_event_paths(change_event: &data::ChangeEvent) -> Vec<path::PathBuf> {
+    match change_event {
+        data::ChangeEvent::File(file_event) => match file_event {
+            data::FileEvent::Create(file_create) => vec![file_create.path().into()],
+            data::FileEvent::Modify(file_modify) => vec![file_modify.path().into()],
+            data::FileEvent::Delete(file_delete) => vec![file_delete.path().into()],
+            data::FileEvent::Move(file_move) => {
+                vec![file_move.from_path().into(), file_move.to_path().into()]
+            }
+            data::FileEvent::UndoDelete(file_undo_delete) => vec![file_undo_delete.path().into()],
+        },
+        data::ChangeEvent::Directory(directory_event) => match directory_event {
+            data::DirectoryEvent::Create(directory_create) => vec![directory_create.path().into()],
+            data::DirectoryEvent::Delete(directory_delete) => vec![directory_delete.path().into()],
+            data::DirectoryEvent::Move(directory_move) => vec![
+                directory_move.from_path().into(),
+                directory_move.to_path().into(),
+            ],
+            data::DirectoryEvent::UndoDelete(directory_undo_delete) => {
+                vec![directory_undo_delete.path().into()]
+            }
+        },
+        data::ChangeEvent::Symlink(_) => vec![],
+    }
+}
+
+pub fn change_event_kind(change_event: &data::ChangeEvent) -> &'static str {
+    match change_event {
+        data::ChangeEvent::File(file_event) => match file_event {
+            data::FileEvent::Create(_) => "file create",
+            data::FileEvent::Modify(_) => "file modify",
+            data::FileEvent::Delete(_) => "file delete",
+            data::FileEvent::Move(_) => "file move",
+            data::FileEvent::UndoDelete(_) => "file undo delete",
+        },
+        data::ChangeEvent::Directory(directory_event) => match directory_event {
+            data::DirectoryEvent::Create(_) => "directory create",
+            data::DirectoryEvent::Delete(_) => "directory delete",
+            data::DirectoryEvent::Move(_) => "directory move",
+            data::DirectoryEvent::UndoDelete(_) => "directory undo delete",
+        },
+        data::ChangeEvent::Symlink(_) => "symlink",
+    }
+}
+
+/// The event's paths joined with ` -> `, for logs and messages.
+pub fn change_event_display_paths(change_event: &data::ChangeEvent) -> String {
+    change_event_paths(change_event)
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
+/// The size of the file contents that follow the event, if any.
+pub fn change_event_size(change_event: &data::ChangeEvent) -> Option<u64> {
+    match change_event {
+        data::ChangeEvent::File(data::FileEvent::Create(file_create)) => Some(file_create.size()),
+        data::ChangeEvent::File(data::FileEvent::Modify(file_modify)) => Some(file_modify.size()),
+        _ => None,
+    }
+}
+
+pub fn change_file_path(
+    file_handler_config: &client_database::FileHandlerConfig,
+    change_id: impl std::fmt::Display,
+) -> path::PathBuf {
+    file_handler_config
+        .program_data_directory
+        .join("changes")
+        .join(format!("{}.tmp", change_id))
+}
+
+/// Local changes recorded in `changes/` that have not been synced to the server yet, indexed by
+/// the relative paths they touch.
+pub struct PendingChanges {
+    changes: HashMap<path::PathBuf, Vec<(path::PathBuf, data::ChangeEvent)>>,
+}
+
+impl PendingChanges {
+    pub fn read(file_handler_config: &client_database::FileHandlerConfig) -> Self {
+        let mut changes: HashMap<path::PathBuf, Vec<(path::PathBuf, data::ChangeEvent)>> =
+            HashMap::new();
+        for (change_id, change_event) in client_database::read_changes(file_handler_config) {
+            let change_file = change_file_path(file_handler_config, change_id);
+            for path in change_event_paths(&change_event) {
+                changes
+                    .entry(path)
+                    .or_default()
+                    .push((change_file.clone(), change_event.clone()));
+            }
+        }
+        Self { changes }
+    }
+
+    #[cfg(test)]
+    pub fn with_paths(paths: &[&str]) -> Self {
+        Self {
+            changes: paths
+                .iter()
+                .map(|path| (path::PathBuf::from(path), Vec::new()))
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn get(&self, path: &path::Path) -> &[(path::PathBuf, data::ChangeEvent)] {
+        self.changes
+            .get(path)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &path::PathBuf> {
+        self.changes.keys()
+    }
+
+    pub fn contains(&self, path: &path::Path) -> bool {
+        self.changes.contains_key(path)
+    }
+
+    pub fn contains_within(&self, directory: &path::Path) -> bool {
+        self.changes.keys().any(|path| path.starts_with(directory))
+    }
+
+    /// Deletes every change file that touches `path`, so the change is never sent to the server.
+    pub fn discard(&mut self, path: &path::Path) -> Result<(), Box<dyn std::error::Error>> {
+        let discarded = match self.changes.remove(path) {
+            Some(discarded) => discarded,
+            None => return Ok(()),
+        };
+        for (change_file, _) in discarded {
+            if change_file.exists() {
+                fs::remove_file(&change_file)?;
+            }
+            for changes in self.changes.values_mut() {
+                changes.retain(|(other_change_file, _)| *other_change_file != change_file);
+            }
+        }
+        self.changes.retain(|_, changes| !changes.is_empty());
+        Ok(())
+    }
+
+    pub fn discard_within(
+        &mut self,
+        directory: &path::Path,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let paths: Vec<path::PathBuf> = self
+            .changes
+            .keys()
+            .filter(|path| path.starts_with(directory))
+            .cloned()
+            .collect();
+        for path in paths {
+            self.discard(&path)?;
+        }
+        Ok(())
+    }
+}
+
+/// A copy of the change counter with its own `changes/`, so `detect` can record changes there
+/// and have them filtered before they reach the real `changes/`. A staging directory left behind
+/// by a crash is picked up again by the next `detect`, so no change is lost or sent unfiltered.
+pub struct Staging {
+    program_data_directory: path::PathBuf,
+    file_handler_config: client_database::FileHandlerConfig,
+}
+
+impl Staging {
+    pub fn open(
+        file_handler_config: &client_database::FileHandlerConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let program_data_directory = file_handler_config.program_data_directory.clone();
+        let mut staging_config = file_handler_config.clone();
+        staging_config.program_data_directory = program_data_directory.join("staging");
+        fs::create_dir_all(staging_config.program_data_directory.join("changes"))?;
+
+        for file_name in ["change_count", "server_version"] {
+            let staged_file = staging_config.program_data_directory.join(file_name);
+            let file = program_data_directory.join(file_name);
+            // A leftover counter is at least as far as the real one.
+            if !staged_file.exists() && file.exists() {
+                fs::copy(&file, &staged_file)?;
+            }
+        }
+
+        Ok(Self {
+            program_data_directory,
+            file_handler_config: staging_config,
+        })
+    }
+
+    pub fn file_handler_config(&self) -> &client_database::FileHandlerConfig {
+        &self.file_handler_config
+    }
+
+    /// Moves the staged changes and the counter into place.
+    pub fn commit(self) -> Result<(), Box<dyn std::error::Error>> {
+        let staging_directory = &self.file_handler_config.program_data_directory;
+        let changes_directory = self.program_data_directory.join("changes");
+        fs::create_dir_all(&changes_directory)?;
+        for entry in fs::read_dir(staging_directory.join("changes"))? {
+            let entry = entry?;
+            fs::rename(entry.path(), changes_directory.join(entry.file_name()))?;
+        }
+        let staged_change_count = staging_directory.join("change_count");
+        if staged_change_count.exists() {
+            fs::rename(
+                &staged_change_count,
+                self.program_data_directory.join("change_count"),
+            )?;
+        }
+        fs::remove_dir_all(staging_directory)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_handler_config(directory: &path::Path) -> client_database::FileHandlerConfig {
+        client_database::FileHandlerConfig {
+            storage_directory: directory.join("storage"),
+            symlink_directory: directory.join("symlink"),
+            temporary_directory: directory.join("temporary"),
+            program_data_directory: directory.join("program_data"),
+        }
+    }
+
+    #[test]
+    fn staged_changes_replace_nothing_until_committed() {
+        let directory = tempfile::tempdir().unwrap();
+        let file_handler_config = file_handler_config(directory.path());
+        let program_data_directory = &file_handler_config.program_data_directory;
+        fs::create_dir_all(program_data_directory.join("changes")).unwrap();
+        fs::write(program_data_directory.join("change_count"), "3").unwrap();
+        fs::write(program_data_directory.join("changes/2.tmp"), "old").unwrap();
+
+        let staging = Staging::open(&file_handler_config).unwrap();
+        let staging_directory = &staging.file_handler_config().program_data_directory;
+        assert_eq!(
+            fs::read_to_string(staging_directory.join("change_count")).unwrap(),
+            "3"
+        );
+        fs::write(staging_directory.join("change_count"), "5").unwrap();
+        fs::write(staging_directory.join("changes/3.tmp"), "kept").unwrap();
+        assert!(!program_data_directory.join("changes/3.tmp").exists());
+
+        staging.commit().unwrap();
+        assert_eq!(
+            fs::read_to_string(program_data_directory.join("changes/2.tmp")).unwrap(),
+            "old"
+        );
+        assert_eq!(
+            fs::read_to_string(program_data_directory.join("changes/3.tmp")).unwrap(),
+            "kept"
+        );
+        assert_eq!(
+            fs::read_to_string(program_data_directory.join("change_count")).unwrap(),
+            "5"
+        );
+        assert!(!program_data_directory.join("staging").exists());
+    }
+
+    #[test]
+    fn leftover_staging_keeps_its_counter() {
+        let directory = tempfile::tempdir().unwrap();
+        let file_handler_config = file_handler_config(directory.path());
+        let program_data_directory = &file_handler_config.program_data_directory;
+        fs::create_dir_all(program_data_directory.join("staging/changes")).unwrap();
+        fs::write(program_data_directory.join("change_count"), "3").unwrap();
+        fs::write(program_data_directory.join("staging/change_count"), "7").unwrap();
+
+        let staging = Staging::open(&file_handler_config).unwrap();
+        assert_eq!(
+            fs::read_to_string(
+                staging
+                    .file_handler_config()
+                    .program_data_directory
+                    .join("change_count")
+            )
+            .unwrap(),
+            "7"
+        );
+    }
+}
@@ -1,17 +1,68 @@
+use std::time;
+
 use hcs_lib::{client_database, config};
 
+use crate::{logging, metrics, rate_limit, selective};
+
 #[derive(serde::Deserialize, Debug, Clone, PartialEq)]
 pub struct ClientConfig {
     #[serde(deserialize_with = "config::parse_log_filter")]
     log_level: log::LevelFilter,
+    #[serde(default)]
+    log_format: logging::LogFormat,
+    #[serde(default)]
+    device_name: Option<String>,
+    #[serde(default)]
+    sync_up_attempts: Option<u32>,
+    #[serde(default)]
+    live_interval_secs: Option<u64>,
 
     tcp_config: TcpConfig,
+    file_handler_config: FileHandlerConfig,
+    #[serde(default)]
+    selective_sync: selective::SelectiveSyncConfig,
+    #[serde(default)]
+    rate_limit: rate_limit::RateLimitConfig,
+    #[serde(default)]
+    metrics: metrics::MetricsConfig,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct FileHandlerConfig {
+    #[serde(flatten)]
     file_handler_config: client_database::FileHandlerConfig,
+    #[serde(default)]
+    merge_globs: Vec<String>,
+    #[serde(default)]
+    max_storage_bytes: Option<u64>,
+    #[serde(default)]
+    cloud_only_downloads: bool,
 }
 
 #[derive(serde::Deserialize, Debug, Clone, PartialEq)]
 pub struct TcpConfig {
-    addr: String,
+    #[serde(default)]
+    addr: Option<String>,
+    #[serde(default)]
+    addrs: Vec<String>,
+    #[serde(default)]
+    pipeline_window: Option<usize>,
+    #[serde(default)]
+    connect_timeout_secs: Option<u64>,
+    #[serde(default)]
+    read_timeout_secs: Option<u64>,
+    #[serde(default)]
+    write_timeout_secs: Option<u64>,
+    #[serde(default)]
+    keepalive_secs: Option<u64>,
+    #[serde(default)]
+    reconnect_attempts: Option<u32>,
+    #[serde(default)]
+    reconnect_initial_delay_ms: Option<u64>,
+    #[serde(default)]
+    reconnect_max_delay_secs: Option<u64>,
+    #[serde(default)]
+    ssh_command: Option<String>,
 }
 
 impl ClientConfig {
@@ -19,17 +70,116 @@ impl ClientConfig {
         self.log_level
     }
 
-    pub fn tcp_addr(&self) -> &str {
-        &self.tcp_config.addr
+    pub fn log_format(&self) -> logging::LogFormat {
+        self.log_format
+    }
+
+    pub fn device_name(&self) -> String {
+        match &self.device_name {
+            Some(device_name) => device_name.clone(),
+            None => std::env::var("HOSTNAME")
+                .or_else(|_| std::env::var("COMPUTERNAME"))
+                .unwrap_or_else(|_| "unknown device".to_string()),
+        }
+    }
+
+    pub fn sync_up_attempts(&self) -> u32 {
+        self.sync_up_attempts.unwrap_or(3).max(1)
+    }
+
+    pub fn live_interval(&self) -> time::Duration {
+        time::Duration::from_secs(self.live_interval_secs.unwrap_or(60).max(1))
+    }
+
+    pub fn tcp_addrs(&self) -> Vec<&str> {
+        self.tcp_config.addrs()
+    }
+
+    pub fn pipeline_window(&self) -> usize {
+        self.tcp_config.pipeline_window.unwrap_or(1).max(1)
+    }
+
+    pub fn connect_timeout(&self) -> time::Duration {
+        time::Duration::from_secs(self.tcp_config.connect_timeout_secs.unwrap_or(10).max(1))
+    }
+
+    pub fn read_timeout(&self) -> time::Duration {
+        time::Duration::from_secs(self.tcp_config.read_timeout_secs.unwrap_or(120).max(1))
+    }
+
+    pub fn write_timeout(&self) -> time::Duration {
+        time::Duration::from_secs(self.tcp_config.write_timeout_secs.unwrap_or(120).max(1))
+    }
+
+    pub fn keepalive(&self) -> time::Duration {
+        time::Duration::from_secs(self.tcp_config.keepalive_secs.unwrap_or(60).max(1))
+    }
+
+    pub fn reconnect_attempts(&self) -> u32 {
+        self.tcp_config.reconnect_attempts.unwrap_or(5).max(1)
+    }
+
+    pub fn reconnect_initial_delay(&self) -> time::Duration {
+        time::Duration::from_millis(self.tcp_config.reconnect_initial_delay_ms.unwrap_or(500))
+    }
+
+    pub fn reconnect_max_delay(&self) -> time::Duration {
+        time::Duration::from_secs(self.tcp_config.reconnect_max_delay_secs.unwrap_or(60))
+    }
+
+    /// The command used for `ssh://` addresses, with `{destination}`, `{port}` and `{target}`
+    /// substituted. It must relay its stdin and stdout to `{target}`.
+    pub fn ssh_command(&self) -> &str {
+        self.tcp_config
+            .ssh_command
+            .as_deref()
+            .unwrap_or("ssh -p {port} -W {target} {destination}")
     }
 
     pub fn file_handler_config(&self) -> &client_database::FileHandlerConfig {
-        &self.file_handler_config
+        &self.file_handler_config.file_handler_config
+    }
+
+    pub fn merge_globs(&self) -> &[String] {
+        &self.file_handler_config.merge_globs
+    }
+
+    pub fn max_storage_bytes(&self) -> Option<u64> {
+        self.file_handler_config.max_storage_bytes
+    }
+
+    /// Whether `sync down` leaves new files on the server as placeholders instead of
+    /// downloading them. Pinned paths are still downloaded.
+    pub fn cloud_only_downloads(&self) -> bool {
+        self.file_handler_config.cloud_only_downloads
+    }
+
+    pub fn selective_sync_config(&self) -> &selective::SelectiveSyncConfig {
+        &self.selective_sync
+    }
+
+    pub fn rate_limit_config(&self) -> &rate_limit::RateLimitConfig {
+        &self.rate_limit
+    }
+
+    /// Limits both directions to `bytes_per_sec`, e.g. from `--limit-rate`.
+    pub fn override_rate_limit(&mut self, bytes_per_sec: u64) {
+        self.rate_limit.override_limit(bytes_per_sec);
+    }
+
+    /// Where live mode serves its metrics, if anywhere.
+    pub fn metrics_listen(&self) -> Option<&str> {
+        self.metrics.listen.as_deref()
     }
 }
 
 impl TcpConfig {
-    pub fn addr(&self) -> &str {
-        &self.addr
+    /// `addr` followed by `addrs`, in the order they should be tried.
+    pub fn addrs(&self) -> Vec<&str> {
+        self.addr
+            .iter()
+            .chain(self.addrs.iter())
+            .map(String::as_str)
+            .collect()
     }
 }
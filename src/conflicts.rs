@@ -0,0 +1,770 @@
+use std::{fs, path, str};
+
+use hcs_lib::{client_database, data};
+
+use chrono::TimeZone;
+
+use crate::{changes, config, diff, merge, placeholders};
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq)]
+pub enum ConflictKind {
+    // Both sides wrote to the file. The local version was moved to the conflict copy.
+    BothModified,
+    // The file was deleted locally but modified on the server. The server version was restored.
+    LocalDeleted,
+    // The file was deleted on the server but modified locally. The local version was kept.
+    RemoteDeleted,
+    // The file was moved to `to_path` on the server but modified locally. The local version was
+    // kept at the old path and the server version downloaded to the new one.
+    RemoteMoved { to_path: path::PathBuf },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Keep {
+    Local,
+    Remote,
+    Both,
+}
+
+impl str::FromStr for Keep {
+    type Err = Box<dyn std::error::Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "local" => Ok(Keep::Local),
+            "remote" => Ok(Keep::Remote),
+            "both" => Ok(Keep::Both),
+            _ => Err(format!("Expected `local`, `remote` or `both`, got `{}`", s).into()),
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq)]
+pub struct Conflict {
+    path: path::PathBuf,
+    conflict_path: Option<path::PathBuf>,
+    kind: ConflictKind,
+    device_name: String,
+    detected_at: i64,
+}
+
+impl Conflict {
+    pub fn path(&self) -> &path::Path {
+        &self.path
+    }
+
+    pub fn conflict_path(&self) -> Option<&path::Path> {
+        self.conflict_path.as_deref()
+    }
+
+    pub fn kind(&self) -> &ConflictKind {
+        &self.kind
+    }
+
+    pub fn device_name(&self) -> &str {
+        &self.device_name
+    }
+
+    pub fn detected_at(&self) -> i64 {
+        self.detected_at
+    }
+}
+
+/// Conflicts that were preserved during `sync down` and still need the user's attention.
+/// Stored in `program_data_directory/conflicts`.
+pub struct ConflictRegistry {
+    registry_path: path::PathBuf,
+    conflicts: Vec<Conflict>,
+}
+
+impl ConflictRegistry {
+    pub fn init(program_data_directory: &path::Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let registry_path = program_data_directory.join("conflicts");
+        let conflicts = if registry_path.exists() {
+            bincode::deserialize(&fs::read(&registry_path)?)?
+        } else {
+            Vec::new()
+        };
+        Ok(Self {
+            registry_path,
+            conflicts,
+        })
+    }
+
+    pub fn conflicts(&self) -> &[Conflict] {
+        &self.conflicts
+    }
+
+    pub fn add(&mut self, conflict: Conflict) -> Result<(), Box<dyn std::error::Error>> {
+        log::warn!(
+            "Sync conflict ({:?}) on `{}`",
+            conflict.kind,
+            conflict.path.display()
+        );
+        self.conflicts.push(conflict);
+        self.save()
+    }
+
+    pub fn find(&self, relative_path: &path::Path) -> Option<&Conflict> {
+        self.conflicts
+            .iter()
+            .find(|conflict| conflict.path == relative_path)
+    }
+
+    pub fn remove(
+        &mut self,
+        relative_path: &path::Path,
+    ) -> Result<Option<Conflict>, Box<dyn std::error::Error>> {
+        let position = self
+            .conflicts
+            .iter()
+            .position(|conflict| conflict.path == relative_path);
+        let conflict = position.map(|position| self.conflicts.remove(position));
+        self.save()?;
+        Ok(conflict)
+    }
+
+    fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        fs::write(&self.registry_path, bincode::serialize(&self.conflicts)?)?;
+        Ok(())
+    }
+}
+
+pub fn conflict_copy_path(
+    relative_path: &path::Path,
+    device_name: &str,
+    date: &str,
+) -> path::PathBuf {
+    let stem = relative_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let file_name = match relative_path.extension() {
+        Some(extension) => format!(
+            "{} (conflict from {} {}).{}",
+            stem,
+            device_name,
+            date,
+            extension.to_string_lossy()
+        ),
+        None => format!("{} (conflict from {} {})", stem, device_name, date),
+    };
+    relative_path.with_file_name(file_name)
+}
+
+/// Checks each incoming server event against the local changes that have not been synced yet,
+/// so that `sync down` never silently overwrites unsynced local edits.
+pub struct ConflictHandler<'a> {
+    file_handler_config: &'a client_database::FileHandlerConfig,
+    device_name: String,
+    pending_changes: changes::PendingChanges,
+    registry: ConflictRegistry,
+    base_store: merge::BaseStore,
+    pending_merge: Option<Conflict>,
+    // Paths the server moved away from under local changes, downloaded once the events are
+    // applied.
+    downloads: Vec<(path::PathBuf, bool)>,
+}
+
+impl<'a> ConflictHandler<'a> {
+    pub fn new(config: &'a config::ClientConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let file_handler_config = config.file_handler_config();
+        Ok(Self {
+            file_handler_config,
+            device_name: config.device_name(),
+            pending_changes: changes::PendingChanges::read(file_handler_config),
+            registry: ConflictRegistry::init(&file_handler_config.program_data_directory)?,
+            base_store: merge::BaseStore::init(file_handler_config, config.merge_globs())?,
+            pending_merge: None,
+            downloads: Vec::new(),
+        })
+    }
+
+    /// Paths to download from the server once the transmission is complete.
+    pub fn take_downloads(&mut self) -> Vec<(path::PathBuf, bool)> {
+        std::mem::take(&mut self.downloads)
+    }
+
+    /// Returns `false` if the server event must not be applied locally.
+    pub fn check(
+        &mut self,
+        change_event: &data::ChangeEvent,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        if self.pending_changes.is_empty() {
+            return Ok(true);
+        }
+
+        match change_event {
+            data::ChangeEvent::File(data::FileEvent::Create(file_create)) => {
+                self.check_file_write(path::Path::new(file_create.path()))?;
+            }
+            data::ChangeEvent::File(data::FileEvent::Modify(file_modify)) => {
+                self.check_file_write(path::Path::new(file_modify.path()))?;
+            }
+            data::ChangeEvent::File(data::FileEvent::Delete(file_delete)) => {
+                let relative_path = path::Path::new(file_delete.path());
+                if self.pending_changes.contains(relative_path) {
+                    self.keep_deleted_on_server(relative_path)?;
+                    self.record(relative_path, None, ConflictKind::RemoteDeleted)?;
+                    return Ok(false);
+                }
+            }
+            data::ChangeEvent::File(data::FileEvent::Move(file_move)) => {
+                let to_path = path::Path::new(file_move.to_path());
+                if self.pending_changes.contains(to_path) {
+                    self.check_file_write(to_path)?;
+                }
+                return self.check_move(path::Path::new(file_move.from_path()), to_path, false);
+            }
+            data::ChangeEvent::Directory(data::DirectoryEvent::Move(directory_move)) => {
+                return self.check_move(
+                    path::Path::new(directory_move.from_path()),
+                    path::Path::new(directory_move.to_path()),
+                    true,
+                );
+            }
+            data::ChangeEvent::Directory(data::DirectoryEvent::Delete(directory_delete)) => {
+                let relative_path = path::Path::new(directory_delete.path());
+                if self.pending_changes.contains_within(relative_path) {
+                    self.keep_deleted_on_server(relative_path)?;
+                    self.record(relative_path, None, ConflictKind::RemoteDeleted)?;
+                    return Ok(false);
+                }
+            }
+            _ => {}
+        }
+
+        Ok(true)
+    }
+
+    // The server no longer has `relative_path`, so the pending changes to it would touch a
+    // missing path. They are dropped along with the custom metadata, and the next `detect`
+    // records the kept local version as new, recreating it on the server.
+    fn keep_deleted_on_server(
+        &mut self,
+        relative_path: &path::Path,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.pending_changes.discard_within(relative_path)?;
+        forget_synced(self.file_handler_config, relative_path)
+    }
+
+    // The pending changes to `from_path` would be sent for a path the server no longer has. The
+    // local version stays where it is and is recreated on the server like a file deleted there,
+    // and the server version is downloaded to `to_path` instead of being moved locally.
+    fn check_move(
+        &mut self,
+        from_path: &path::Path,
+        to_path: &path::Path,
+        is_directory: bool,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        if !self.pending_changes.contains_within(from_path) {
+            return Ok(true);
+        }
+
+        self.keep_deleted_on_server(from_path)?;
+        self.downloads.push((to_path.to_path_buf(), is_directory));
+        self.record(
+            from_path,
+            None,
+            ConflictKind::RemoteMoved {
+                to_path: to_path.to_path_buf(),
+            },
+        )?;
+        Ok(false)
+    }
+
+    fn check_file_write(
+        &mut self,
+        relative_path: &path::Path,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.pending_changes.contains(relative_path) {
+            return Ok(());
+        }
+
+        let file_paths = client_database::FilePaths::from_relative_path(
+            relative_path.to_path_buf(),
+            client_database::Type::File,
+            client_database::FileLocation::StorageDir,
+            None,
+            self.file_handler_config,
+        )?;
+
+        if file_paths.storage_dir_path().exists() {
+            // Keep the local version next to the server version. No custom metadata is written
+            // for the copy, so the next `detect` records it as a new file.
+            let date = chrono::Local::now().format("%Y-%m-%d").to_string();
+            let mut conflict_path = conflict_copy_path(relative_path, &self.device_name, &date);
+            let mut n = 1;
+            while self
+                .file_handler_config
+                .storage_directory
+                .join(&conflict_path)
+                .exists()
+            {
+                n += 1;
+                conflict_path = conflict_copy_path(
+                    relative_path,
+                    &self.device_name,
+                    &format!("{} {}", date, n),
+                );
+            }
+
+            let conflict_file_paths = client_database::FilePaths::from_relative_path(
+                conflict_path.clone(),
+                client_database::Type::File,
+                client_database::FileLocation::StorageDir,
+                None,
+                self.file_handler_config,
+            )?;
+            fs::rename(
+                &file_paths.storage_dir_path(),
+                &conflict_file_paths.storage_dir_path(),
+            )?;
+            symlink::symlink_file(
+                &conflict_file_paths.storage_dir_path(),
+                &conflict_file_paths.symlink_dir_path(),
+            )?;
+
+            self.pending_changes.discard(relative_path)?;
+            let conflict = self.conflict(
+                relative_path,
+                Some(conflict_path),
+                ConflictKind::BothModified,
+            );
+            if self.base_store.is_mergeable(relative_path) {
+                // `applied` merges the copy back once the server version is written, and only
+                // records the conflict if that fails.
+                self.pending_merge = Some(conflict);
+                Ok(())
+            } else {
+                self.registry.add(conflict)
+            }
+        } else {
+            // Deleted locally, the server version wins and is written back in place.
+            if fs::read_link(&file_paths.symlink_dir_path()).is_err() {
+                symlink::symlink_file(
+                    &file_paths.storage_dir_path(),
+                    &file_paths.symlink_dir_path(),
+                )?;
+            }
+
+            self.pending_changes.discard(relative_path)?;
+            self.record(relative_path, None, ConflictKind::LocalDeleted)
+        }
+    }
+
+    /// Must be called once the server event has been written locally.
+    pub fn applied(
+        &mut self,
+        change_event: &data::ChangeEvent,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // Read the base before it is replaced by the server version.
+        let base = self
+            .pending_merge
+            .as_ref()
+            .and_then(|conflict| self.base_store.read(&conflict.path));
+        self.base_store.update(change_event)?;
+
+        let conflict = match self.pending_merge.take() {
+            Some(conflict) => conflict,
+            None => return Ok(()),
+        };
+        if let (Some(base), Some(conflict_path)) = (base, &conflict.conflict_path) {
+            let merged = merge_conflict_copy(
+                &base,
+                &self
+                    .file_handler_config
+                    .storage_directory
+                    .join(&conflict.path),
+                &self
+                    .file_handler_config
+                    .storage_directory
+                    .join(conflict_path),
+            )?;
+            if merged {
+                remove_local(self.file_handler_config, conflict_path)?;
+                log::info!(
+                    "Merged local and server changes to `{}`",
+                    conflict.path.display()
+                );
+                return Ok(());
+            }
+        }
+
+        self.registry.add(conflict)
+    }
+
+    fn conflict(
+        &self,
+        relative_path: &path::Path,
+        conflict_path: Option<path::PathBuf>,
+        kind: ConflictKind,
+    ) -> Conflict {
+        Conflict {
+            path: relative_path.to_path_buf(),
+            conflict_path,
+            kind,
+            device_name: self.device_name.clone(),
+            detected_at: chrono::Local::now().timestamp(),
+        }
+    }
+
+    fn record(
+        &mut self,
+        relative_path: &path::Path,
+        conflict_path: Option<path::PathBuf>,
+        kind: ConflictKind,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let conflict = self.conflict(relative_path, conflict_path, kind);
+        self.registry.add(conflict)
+    }
+}
+
+// Merges the local version kept at `conflict_storage_path` into the server version at
+// `storage_path`. Returns `false`, leaving both files untouched, if either is not text or both
+// changed the same lines.
+fn merge_conflict_copy(
+    base: &str,
+    storage_path: &path::Path,
+    conflict_storage_path: &path::Path,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let remote = fs::read_to_string(storage_path);
+    let local = fs::read_to_string(conflict_storage_path);
+    if let (Ok(remote), Ok(local)) = (remote, local) {
+        if let Some(merged) = merge::merge3(base, &local, &remote) {
+            // Written after the custom metadata, so the next `detect` records the merge as a
+            // local modification.
+            fs::write(storage_path, merged)?;
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// Removes the custom metadata of `relative_path` and everything below it, so `detect` treats
+// them as never synced.
+fn forget_synced(
+    file_handler_config: &client_database::FileHandlerConfig,
+    relative_path: &path::Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file_paths = client_database::FilePaths::from_relative_path(
+        relative_path.to_path_buf(),
+        client_database::Type::File,
+        client_database::FileLocation::StorageDir,
+        None,
+        file_handler_config,
+    )?;
+    if file_paths.custom_metadata_path().exists() {
+        fs::remove_file(&file_paths.custom_metadata_path())?;
+    }
+    if file_paths.storage_dir_path().is_dir() {
+        forget_synced_within(&file_paths.storage_dir_path())?;
+    }
+    Ok(())
+}
+
+fn forget_synced_within(directory: &path::Path) -> Result<(), Box<dyn std::error::Error>> {
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type()?.is_dir() {
+            forget_synced_within(&entry.path())?;
+        } else if file_name.starts_with('.') && file_name.ends_with(".sc") {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+fn remove_local(
+    file_handler_config: &client_database::FileHandlerConfig,
+    relative_path: &path::Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file_paths = client_database::FilePaths::from_relative_path(
+        relative_path.to_path_buf(),
+        client_database::Type::File,
+        client_database::FileLocation::StorageDir,
+        None,
+        file_handler_config,
+    )?;
+
+    let mut pending_changes = changes::PendingChanges::read(file_handler_config);
+    pending_changes.discard_within(relative_path)?;
+
+    // The custom metadata file is left in place, so the next `detect` records a deletion for
+    // anything that was already on the server.
+    if file_paths.storage_dir_path().is_dir() {
+        fs::remove_dir_all(&file_paths.storage_dir_path())?;
+        if file_paths.symlink_dir_path().exists() {
+            fs::remove_dir_all(&file_paths.symlink_dir_path())?;
+        }
+    } else {
+        if file_paths.storage_dir_path().exists() {
+            fs::remove_file(&file_paths.storage_dir_path())?;
+        }
+        if fs::read_link(&file_paths.symlink_dir_path()).is_ok() {
+            symlink::remove_symlink_file(&file_paths.symlink_dir_path())?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn list_conflicts(config: &config::ClientConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let registry = ConflictRegistry::init(&config.file_handler_config().program_data_directory)?;
+    if registry.conflicts().is_empty() {
+        println!("No conflicts.");
+        return Ok(());
+    }
+
+    for conflict in registry.conflicts() {
+        let detected_at = chrono::Local
+            .timestamp_opt(conflict.detected_at, 0)
+            .single()
+            .map(|detected_at| detected_at.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default();
+        let description = match (&conflict.kind, &conflict.conflict_path) {
+            (ConflictKind::BothModified, Some(conflict_path)) => format!(
+                "modified on both sides, local version kept as `{}`",
+                conflict_path.display()
+            ),
+            (ConflictKind::BothModified, None) => "modified on both sides".to_string(),
+            (ConflictKind::LocalDeleted, _) => {
+                "deleted locally but modified on the server, server version restored".to_string()
+            }
+            (ConflictKind::RemoteDeleted, _) => {
+                "deleted on the server but modified locally, local version kept".to_string()
+            }
+            (ConflictKind::RemoteMoved { to_path }, _) => format!(
+                "moved to `{}` on the server but modified locally, local version kept",
+                to_path.display()
+            ),
+        };
+        println!(
+            "{}\t{}\t({} {})",
+            conflict.path.display(),
+            description,
+            conflict.device_name,
+            detected_at
+        );
+    }
+
+    Ok(())
+}
+
+pub fn resolve_conflict(
+    config: &config::ClientConfig,
+    relative_path: &path::Path,
+    keep: Keep,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file_handler_config = config.file_handler_config();
+    let mut registry = ConflictRegistry::init(&file_handler_config.program_data_directory)?;
+    let conflict = match registry.find(relative_path) {
+        Some(conflict) => conflict.clone(),
+        None => {
+            return Err(format!("No conflict recorded for `{}`", relative_path.display()).into())
+        }
+    };
+
+    match (&conflict.kind, keep) {
+        (_, Keep::Both) => {}
+        (ConflictKind::BothModified, Keep::Local) => {
+            if let Some(conflict_path) = &conflict.conflict_path {
+                // Copy rather than rename, so the file is newer than its custom metadata and
+                // the next `detect` records a modification.
+                fs::copy(
+                    file_handler_config.storage_directory.join(conflict_path),
+                    file_handler_config.storage_directory.join(&conflict.path),
+                )?;
+                remove_local(file_handler_config, conflict_path)?;
+            }
+        }
+        (ConflictKind::BothModified, Keep::Remote) => {
+            if let Some(conflict_path) = &conflict.conflict_path {
+                remove_local(file_handler_config, conflict_path)?;
+            }
+        }
+        (ConflictKind::LocalDeleted, Keep::Local) => {
+            remove_local(file_handler_config, &conflict.path)?;
+        }
+        (ConflictKind::LocalDeleted, Keep::Remote) => {}
+        (ConflictKind::RemoteDeleted | ConflictKind::RemoteMoved { .. }, Keep::Local) => {}
+        (ConflictKind::RemoteDeleted | ConflictKind::RemoteMoved { .. }, Keep::Remote) => {
+            // The server no longer has it, so `detect` must not record a deletion to send.
+            remove_local(file_handler_config, &conflict.path)?;
+            forget_synced(file_handler_config, &conflict.path)?;
+        }
+    }
+
+    registry.remove(relative_path)?;
+    log::info!("Resolved conflict on `{}`", relative_path.display());
+    Ok(())
+}
+
+pub fn diff_conflict(
+    config: &config::ClientConfig,
+    relative_path: &path::Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file_handler_config = config.file_handler_config();
+    let registry = ConflictRegistry::init(&file_handler_config.program_data_directory)?;
+    let conflict = match registry.find(relative_path) {
+        Some(conflict) => conflict,
+        None => {
+            return Err(format!("No conflict recorded for `{}`", relative_path.display()).into())
+        }
+    };
+    let conflict_path = match &conflict.conflict_path {
+        Some(conflict_path) => conflict_path,
+        None => return Err("Only conflicts modified on both sides can be diffed".into()),
+    };
+
+    let remote = fs::read_to_string(placeholders::ensure_hydrated(config, &conflict.path)?)
+        .map_err(|_| "Server version is not a text file")?;
+    let local = fs::read_to_string(file_handler_config.storage_directory.join(conflict_path))
+        .map_err(|_| "Local version is not a text file")?;
+
+    diff::print_diff(
+        &format!("{} (remote)", conflict.path.display()),
+        &format!("{} (local)", conflict_path.display()),
+        &remote,
+        &local,
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conflict_copy_keeps_the_extension() {
+        assert_eq!(
+            conflict_copy_path(path::Path::new("notes/todo.txt"), "laptop", "2023-05-07"),
+            path::Path::new("notes/todo (conflict from laptop 2023-05-07).txt")
+        );
+        assert_eq!(
+            conflict_copy_path(path::Path::new("Makefile"), "laptop", "2023-05-07 2"),
+            path::Path::new("Makefile (conflict from laptop 2023-05-07 2)")
+        );
+    }
+
+    #[test]
+    fn non_overlapping_edits_are_merged_into_the_server_version() {
+        let directory = tempfile::tempdir().unwrap();
+        let storage_path = directory.path().join("notes.txt");
+        let conflict_storage_path = directory.path().join("notes (conflict).txt");
+        fs::write(&storage_path, "one\ntwo\nthree 3\n").unwrap();
+        fs::write(&conflict_storage_path, "one 1\ntwo\nthree\n").unwrap();
+
+        let merged =
+            merge_conflict_copy("one\ntwo\nthree\n", &storage_path, &conflict_storage_path)
+                .unwrap();
+
+        assert!(merged);
+        assert_eq!(
+            fs::read_to_string(&storage_path).unwrap(),
+            "one 1\ntwo\nthree 3\n"
+        );
+    }
+
+    #[test]
+    fn overlapping_edits_keep_the_conflict_copy() {
+        let directory = tempfile::tempdir().unwrap();
+        let storage_path = directory.path().join("notes.txt");
+        let conflict_storage_path = directory.path().join("notes (conflict).txt");
+        fs::write(&storage_path, "one\ntwo remote\nthree\n").unwrap();
+        fs::write(&conflict_storage_path, "one\ntwo local\nthree\n").unwrap();
+
+        let merged =
+            merge_conflict_copy("one\ntwo\nthree\n", &storage_path, &conflict_storage_path)
+                .unwrap();
+
+        assert!(!merged);
+        assert_eq!(
+            fs::read_to_string(&storage_path).unwrap(),
+            "one\ntwo remote\nthree\n"
+        );
+        assert_eq!(
+            fs::read_to_string(&conflict_storage_path).unwrap(),
+            "one\ntwo local\nthree\n"
+        );
+    }
+
+    #[test]
+    fn keep_parses_each_side() {
+        assert_eq!("local".parse::<Keep>().unwrap(), Keep::Local);
+        assert_eq!("remote".parse::<Keep>().unwrap(), Keep::Remote);
+        assert_eq!("both".parse::<Keep>().unwrap(), Keep::Both);
+        assert!("".parse::<Keep>().is_err());
+        assert!("mine".parse::<Keep>().is_err());
+    }
+
+    #[test]
+    fn server_move_keeps_local_changes_at_the_old_path() {
+        let directory = tempfile::tempdir().unwrap();
+        let file_handler_config = client_database::FileHandlerConfig {
+            storage_directory: directory.path().join("storage"),
+            symlink_directory: directory.path().join("symlink"),
+            temporary_directory: directory.path().join("temporary"),
+            program_data_directory: directory.path().join("program_data"),
+        };
+        fs::create_dir_all(&file_handler_config.program_data_directory).unwrap();
+        let mut conflict_handler = ConflictHandler {
+            file_handler_config: &file_handler_config,
+            device_name: "laptop".to_string(),
+            pending_changes: changes::PendingChanges::with_paths(&["notes/todo.txt"]),
+            registry: ConflictRegistry::init(&file_handler_config.program_data_directory).unwrap(),
+            base_store: merge::BaseStore::init(&file_handler_config, &[]).unwrap(),
+            pending_merge: None,
+            downloads: Vec::new(),
+        };
+
+        let to_path = path::Path::new("archive/todo.txt");
+        assert!(conflict_handler
+            .check_move(path::Path::new("docs"), path::Path::new("old docs"), true)
+            .unwrap());
+        assert!(!conflict_handler
+            .check_move(path::Path::new("notes/todo.txt"), to_path, false)
+            .unwrap());
+
+        assert!(!conflict_handler
+            .pending_changes
+            .contains(path::Path::new("notes/todo.txt")));
+        assert_eq!(
+            conflict_handler.take_downloads(),
+            [(to_path.to_path_buf(), false)]
+        );
+        let conflicts = ConflictRegistry::init(&file_handler_config.program_data_directory)
+            .unwrap()
+            .conflicts()
+            .to_vec();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].path(), path::Path::new("notes/todo.txt"));
+        assert_eq!(
+            conflicts[0].kind(),
+            &ConflictKind::RemoteMoved {
+                to_path: to_path.to_path_buf()
+            }
+        );
+    }
+
+    #[test]
+    fn forgetting_a_directory_removes_all_metadata_below_it() {
+        let directory = tempfile::tempdir().unwrap();
+        let nested = directory.path().join("nested");
+        fs::create_dir(&nested).unwrap();
+        fs::write(directory.path().join("a.txt"), "a").unwrap();
+        fs::write(directory.path().join(".a.txt.sc"), "{}").unwrap();
+        fs::write(directory.path().join(".nested.sc"), "{}").unwrap();
+        fs::write(nested.join("b.txt"), "b").unwrap();
+        fs::write(nested.join(".b.txt.sc"), "{}").unwrap();
+
+        forget_synced_within(directory.path()).unwrap();
+
+        assert!(directory.path().join("a.txt").exists());
+        assert!(nested.join("b.txt").exists());
+        assert!(!directory.path().join(".a.txt.sc").exists());
+        assert!(!directory.path().join(".nested.sc").exists());
+        assert!(!nested.join(".b.txt.sc").exists());
+    }
+}
@@ -0,0 +1,254 @@
+use std::{
+    future, path, process,
+    sync::{Arc, Mutex},
+};
+
+use hcs_lib::client_database;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{config, engine::AsyncResult};
+
+/// A command for the running `hcs live`, sent as one JSON line over its control socket.
+#[derive(Debug, serde::Serialize, serde::Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Request {
+    Status,
+    Pause,
+    Resume,
+    SyncNow(SyncDirection),
+}
+
+/// Which way `hcs sync` or `hcs sync-now` asked the running `hcs live` to sync.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SyncDirection {
+    Up,
+    Down,
+    Both,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Response {
+    Status(DaemonStatus),
+    Done,
+    Failed(String),
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Default)]
+pub struct DaemonStatus {
+    pub pid: u32,
+    pub paused: bool,
+    pub syncing: bool,
+    pub last_sync: Option<i64>,
+    pub last_error: Option<String>,
+}
+
+/// State shared between live mode and its control socket. Syncs requested over the socket are
+/// handed to the live loop so they never run alongside its own.
+pub(crate) struct Control {
+    status: Mutex<DaemonStatus>,
+    sync_requests: mpsc::Sender<(SyncDirection, oneshot::Sender<Response>)>,
+}
+
+impl Control {
+    pub(crate) fn new(
+        sync_requests: mpsc::Sender<(SyncDirection, oneshot::Sender<Response>)>,
+    ) -> Self {
+        Self {
+            status: Mutex::new(DaemonStatus {
+                pid: process::id(),
+                ..Default::default()
+            }),
+            sync_requests,
+        }
+    }
+
+    pub(crate) fn is_paused(&self) -> bool {
+        self.status.lock().unwrap().paused
+    }
+
+    pub(crate) fn sync_started(&self) {
+        self.status.lock().unwrap().syncing = true;
+    }
+
+    pub(crate) fn sync_finished(&self, error: Option<String>) {
+        let mut status = self.status.lock().unwrap();
+        status.syncing = false;
+        if error.is_none() {
+            status.last_sync = Some(chrono::Local::now().timestamp());
+        }
+        status.last_error = error;
+    }
+
+    async fn handle(&self, request: Request) -> Response {
+        match request {
+            Request::Status => Response::Status(self.status.lock().unwrap().clone()),
+            Request::Pause | Request::Resume => {
+                self.status.lock().unwrap().paused = request == Request::Pause;
+                Response::Done
+            }
+            Request::SyncNow(direction) => {
+                let (reply, response) = oneshot::channel();
+                if self.sync_requests.send((direction, reply)).await.is_err() {
+                    return Response::Failed("Live mode is stopping".to_string());
+                }
+                response
+                    .await
+                    .unwrap_or_else(|_| Response::Failed("Live mode is stopping".to_string()))
+            }
+        }
+    }
+}
+
+pub(crate) fn socket_path(
+    file_handler_config: &client_database::FileHandlerConfig,
+) -> path::PathBuf {
+    file_handler_config
+        .program_data_directory
+        .join("control.sock")
+}
+
+#[cfg(unix)]
+async fn respond(stream: tokio::net::UnixStream, control: Arc<Control>) -> AsyncResult<()> {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+    let (reader, mut writer) = stream.into_split();
+    let mut line = String::new();
+    tokio::io::BufReader::new(reader)
+        .read_line(&mut line)
+        .await?;
+    let response = match serde_json::from_str(&line) {
+        Ok(request) => control.handle(request).await,
+        Err(err) => Response::Failed(format!("Invalid request: {}", err)),
+    };
+    let mut line = serde_json::to_string(&response)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await?;
+    Ok(())
+}
+
+/// Binds the control socket, failing if another live mode is answering on it, and returns the
+/// future serving it.
+#[cfg(unix)]
+pub(crate) fn listen(
+    socket_path: &path::Path,
+    control: Arc<Control>,
+) -> AsyncResult<impl future::Future<Output = AsyncResult<()>>> {
+    // A socket nobody answers on was left behind by a live mode that did not stop cleanly.
+    if socket_path.exists() {
+        if std::os::unix::net::UnixStream::connect(socket_path).is_ok() {
+            return Err(format!(
+                "Another hcs live is already listening on {}",
+                socket_path.display()
+            )
+            .into());
+        }
+        std::fs::remove_file(socket_path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(socket_path)?;
+    log::debug!("Listening for commands on {}", socket_path.display());
+    Ok(async move {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let control = Arc::clone(&control);
+            tokio::spawn(async move {
+                if let Err(err) = respond(stream, control).await {
+                    log::warn!("Control request failed: {}", err);
+                }
+            });
+        }
+    })
+}
+
+#[cfg(not(unix))]
+pub(crate) fn listen(
+    _socket_path: &path::Path,
+    _control: Arc<Control>,
+) -> AsyncResult<impl future::Future<Output = AsyncResult<()>>> {
+    log::warn!("Unix sockets are not supported on this platform, live mode can't be controlled");
+    Ok(future::pending())
+}
+
+/// Sends `request` to the running `hcs live`. Returns `None` when none is running.
+#[cfg(unix)]
+pub fn request(
+    config: &config::ClientConfig,
+    request: &Request,
+) -> Result<Option<Response>, Box<dyn std::error::Error>> {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        os::unix::net::UnixStream,
+    };
+
+    let mut stream = match UnixStream::connect(socket_path(config.file_handler_config())) {
+        Ok(stream) => stream,
+        Err(_) => return Ok(None),
+    };
+    let mut line = serde_json::to_string(request)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    if line.is_empty() {
+        return Err("hcs live closed the control socket without answering".into());
+    }
+    Ok(Some(serde_json::from_str(&line)?))
+}
+
+#[cfg(not(unix))]
+pub fn request(
+    _config: &config::ClientConfig,
+    _request: &Request,
+) -> Result<Option<Response>, Box<dyn std::error::Error>> {
+    Ok(None)
+}
+
+/// The status of the running `hcs live`, if any.
+pub fn daemon_status(
+    config: &config::ClientConfig,
+) -> Result<Option<DaemonStatus>, Box<dyn std::error::Error>> {
+    match request(config, &Request::Status)? {
+        Some(Response::Status(daemon_status)) => Ok(Some(daemon_status)),
+        Some(Response::Failed(err)) => Err(err.into()),
+        Some(Response::Done) => Err("Unexpected answer from hcs live".into()),
+        None => Ok(None),
+    }
+}
+
+/// Sends `request` to the running `hcs live`, failing if there is none.
+pub fn command(
+    config: &config::ClientConfig,
+    request: Request,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match self::request(config, &request)? {
+        Some(Response::Failed(err)) => Err(err.into()),
+        Some(_) => Ok(()),
+        None => Err("hcs live is not running".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn sync_requests_keep_their_direction() {
+        assert_eq!(
+            serde_json::to_string(&Request::SyncNow(SyncDirection::Up)).unwrap(),
+            r#"{"sync-now":"up"}"#
+        );
+
+        let (sync_requests, mut sync_requests_rx) = mpsc::channel(1);
+        let control = Arc::new(Control::new(sync_requests));
+        let handled = tokio::spawn({
+            let control = Arc::clone(&control);
+            async move { control.handle(Request::SyncNow(SyncDirection::Down)).await }
+        });
+        let (direction, reply) = sync_requests_rx.recv().await.unwrap();
+        assert_eq!(direction, SyncDirection::Down);
+        reply.send(Response::Done).unwrap();
+        assert!(matches!(handled.await.unwrap(), Response::Done));
+    }
+}
@@ -0,0 +1,185 @@
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiffOp<'a> {
+    Equal(&'a str),
+    Delete(&'a str),
+    Insert(&'a str),
+}
+
+/// Line based diff of `old` against `new` with Myers' algorithm in its linear space form, so large
+/// files need memory proportional to their length rather than the product of both lengths.
+pub fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<DiffOp<'a>> {
+    let mut ops = Vec::with_capacity(old.len().max(new.len()));
+    diff_range(old, new, &mut ops);
+    ops
+}
+
+fn diff_range<'a>(old: &[&'a str], new: &[&'a str], ops: &mut Vec<DiffOp<'a>>) {
+    let prefix = old
+        .iter()
+        .zip(new)
+        .take_while(|(old_line, new_line)| old_line == new_line)
+        .count();
+    ops.extend(old[..prefix].iter().map(|line| DiffOp::Equal(line)));
+    let (old, new) = (&old[prefix..], &new[prefix..]);
+
+    let suffix = old
+        .iter()
+        .rev()
+        .zip(new.iter().rev())
+        .take_while(|(old_line, new_line)| old_line == new_line)
+        .count();
+    let (old_middle, new_middle) = (&old[..old.len() - suffix], &new[..new.len() - suffix]);
+
+    match middle_snake(old_middle, new_middle) {
+        Some((x, y)) => {
+            diff_range(&old_middle[..x], &new_middle[..y], ops);
+            diff_range(&old_middle[x..], &new_middle[y..], ops);
+        }
+        None => {
+            ops.extend(old_middle.iter().map(|line| DiffOp::Delete(line)));
+            ops.extend(new_middle.iter().map(|line| DiffOp::Insert(line)));
+        }
+    }
+
+    ops.extend(
+        old[old.len() - suffix..]
+            .iter()
+            .map(|line| DiffOp::Equal(line)),
+    );
+}
+
+// Finds where a shortest edit script crosses the middle by searching from both ends at once, and
+// returns the start of the snake there. `None` if there is nothing left to split.
+fn middle_snake(old: &[&str], new: &[&str]) -> Option<(usize, usize)> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    if n == 0 || m == 0 {
+        return None;
+    }
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    let max = (n + m + 1) / 2;
+    let offset = max + 1;
+    // Furthest x reached on each diagonal k = x - y, from the start and from the end respectively.
+    let mut forward = vec![0isize; 2 * offset as usize + 1];
+    let mut backward = vec![0isize; 2 * offset as usize + 1];
+    let index = |k: isize| (k + offset) as usize;
+
+    for d in 0..=max {
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && forward[index(k - 1)] < forward[index(k + 1)]) {
+                forward[index(k + 1)]
+            } else {
+                forward[index(k - 1)] + 1
+            };
+            let mut y = x - k;
+            let (x_start, y_start) = (x, y);
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[index(k)] = x;
+            if odd && (delta - k).abs() < d && x + backward[index(delta - k)] >= n {
+                return split(x_start, y_start, n, m);
+            }
+        }
+
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && backward[index(k - 1)] < backward[index(k + 1)]) {
+                backward[index(k + 1)]
+            } else {
+                backward[index(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[(n - x - 1) as usize] == new[(m - y - 1) as usize] {
+                x += 1;
+                y += 1;
+            }
+            backward[index(k)] = x;
+            if !odd && (delta - k).abs() <= d && x + forward[index(delta - k)] >= n {
+                return split(n - x, m - y, n, m);
+            }
+        }
+    }
+    None
+}
+
+// A split at either end would not make the ranges any smaller.
+fn split(x: isize, y: isize, n: isize, m: isize) -> Option<(usize, usize)> {
+    if (x, y) == (0, 0) || (x, y) == (n, m) {
+        return None;
+    }
+    Some((x as usize, y as usize))
+}
+
+pub fn print_diff(old_name: &str, new_name: &str, old: &str, new: &str) {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+
+    println!("--- {}", old_name);
+    println!("+++ {}", new_name);
+    for op in diff_lines(&old_lines, &new_lines) {
+        match op {
+            DiffOp::Equal(line) => println!(" {}", line),
+            DiffOp::Delete(line) => println!("-{}", line),
+            DiffOp::Insert(line) => println!("+{}", line),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply<'a>(ops: &[DiffOp<'a>]) -> (Vec<&'a str>, Vec<&'a str>) {
+        let (mut old, mut new) = (Vec::new(), Vec::new());
+        for op in ops {
+            match op {
+                DiffOp::Equal(line) => {
+                    old.push(*line);
+                    new.push(*line);
+                }
+                DiffOp::Delete(line) => old.push(*line),
+                DiffOp::Insert(line) => new.push(*line),
+            }
+        }
+        (old, new)
+    }
+
+    fn edits(ops: &[DiffOp]) -> usize {
+        ops.iter()
+            .filter(|op| !matches!(op, DiffOp::Equal(_)))
+            .count()
+    }
+
+    #[test]
+    fn diff_is_a_shortest_edit_script() {
+        let old = ["a", "b", "c", "a", "b", "b", "a"];
+        let new = ["c", "b", "a", "b", "a", "c"];
+        let ops = diff_lines(&old, &new);
+        assert_eq!(apply(&ops), (old.to_vec(), new.to_vec()));
+        // The example from Myers' paper, with an edit distance of 5.
+        assert_eq!(edits(&ops), 5);
+
+        assert_eq!(
+            diff_lines(&["a", "b"], &["a", "x", "b"]),
+            [DiffOp::Equal("a"), DiffOp::Insert("x"), DiffOp::Equal("b")]
+        );
+        assert_eq!(diff_lines(&[], &["a"]), [DiffOp::Insert("a")]);
+        assert_eq!(
+            diff_lines(&["a"], &["b"]),
+            [DiffOp::Delete("a"), DiffOp::Insert("b")]
+        );
+    }
+
+    #[test]
+    fn large_inputs_diff_in_linear_space() {
+        let lines: Vec<String> = (0..200_000).map(|line| line.to_string()).collect();
+        let old: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let mut new = old.clone();
+        new[1_000] = "changed";
+        new.remove(150_000);
+        let ops = diff_lines(&old, &new);
+        assert_eq!(apply(&ops), (old, new));
+        assert_eq!(edits(&ops), 3);
+    }
+}
@@ -0,0 +1,128 @@
+use std::fs;
+
+use hcs_lib::{client_database, data};
+
+use crate::{
+    bytes_to_transmission_type, changes, config, extra_data, open_connection, status,
+    transmission_type_to_bytes,
+};
+
+fn query_pending_events(
+    config: &config::ClientConfig,
+    server_version: i32,
+) -> Result<Vec<data::ChangeEvent>, Box<dyn std::error::Error>> {
+    let mut tcp_connection = open_connection(config)?;
+
+    let transmission =
+        data::Transmission::ExtraData(extra_data::ExtraData::PendingEventsQuery { server_version });
+    let bytes = transmission_type_to_bytes(transmission)?;
+    tcp_connection.write(&bytes)?;
+
+    let response = tcp_connection.read_next_chunk()?;
+    match bytes_to_transmission_type(&response)? {
+        data::Transmission::ExtraData(extra_data::ExtraData::PendingEvents(change_events)) => {
+            Ok(change_events)
+        }
+        _ => Err("Server did not respond with PendingEvents".into()),
+    }
+}
+
+fn describe(change_event: &data::ChangeEvent, transfer: &str, size: Option<u64>) -> String {
+    let paths: Vec<String> = changes::change_event_paths(change_event)
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+    let action = match change_event {
+        data::ChangeEvent::File(data::FileEvent::Create(_))
+        | data::ChangeEvent::File(data::FileEvent::Modify(_)) => transfer,
+        data::ChangeEvent::File(data::FileEvent::Delete(_))
+        | data::ChangeEvent::Directory(data::DirectoryEvent::Delete(_)) => "delete",
+        data::ChangeEvent::File(data::FileEvent::Move(_))
+        | data::ChangeEvent::Directory(data::DirectoryEvent::Move(_)) => "move",
+        data::ChangeEvent::Directory(data::DirectoryEvent::Create(_)) => "mkdir",
+        _ => changes::change_event_kind(change_event),
+    };
+    match size {
+        Some(size) => format!("{:<10}{} ({} bytes)", action, paths.join(" -> "), size),
+        None => format!("{:<10}{}", action, paths.join(" -> ")),
+    }
+}
+
+fn dry_run_up(file_handler_config: &client_database::FileHandlerConfig) {
+    let changes = data::optimize_changes(client_database::read_changes(file_handler_config));
+    // Only what `detect` already recorded. Detecting here would write change files.
+    println!("sync up: {} changes would be sent", changes.len());
+    for (_, change_event) in changes {
+        let size = match &change_event {
+            data::ChangeEvent::File(data::FileEvent::Create(file_create)) => {
+                Some(file_create.path())
+            }
+            data::ChangeEvent::File(data::FileEvent::Modify(file_modify)) => {
+                Some(file_modify.path())
+            }
+            _ => None,
+        }
+        .and_then(|path| fs::metadata(file_handler_config.storage_directory.join(path)).ok())
+        .map(|metadata| metadata.len());
+        println!("  {}", describe(&change_event, "upload", size));
+    }
+}
+
+fn dry_run_down(
+    config: &config::ClientConfig,
+    server_version: i32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let change_events = query_pending_events(config, server_version)?;
+    let pending_changes = changes::PendingChanges::read(config.file_handler_config());
+
+    println!(
+        "sync down: {} changes would be received",
+        change_events.len()
+    );
+    for change_event in change_events {
+        let size = match &change_event {
+            data::ChangeEvent::File(data::FileEvent::Create(file_create)) => {
+                Some(file_create.size())
+            }
+            data::ChangeEvent::File(data::FileEvent::Modify(file_modify)) => {
+                Some(file_modify.size())
+            }
+            _ => None,
+        };
+        let conflicts = changes::change_event_paths(&change_event)
+            .iter()
+            .any(|path| pending_changes.contains(path));
+        if conflicts {
+            println!(
+                "  {} [conflicts with a local change]",
+                describe(&change_event, "download", size)
+            );
+        } else {
+            println!("  {}", describe(&change_event, "download", size));
+        }
+    }
+    Ok(())
+}
+
+/// Prints what `sync` would do, without writing files, recording or deleting change files or
+/// advancing the server version. Local edits not yet recorded by `detect` are not listed.
+pub fn dry_run(
+    config: &config::ClientConfig,
+    up: bool,
+    down: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let server_version =
+        client_database::ServerVersion::init(&config.file_handler_config().program_data_directory)
+            .server_version();
+
+    if down {
+        dry_run_down(config, server_version)?;
+    } else if status::query_server_version(config)? > server_version {
+        println!("Server is ahead, sync up would first sync down:");
+        dry_run_down(config, server_version)?;
+    }
+    if up {
+        dry_run_up(config.file_handler_config());
+    }
+    Ok(())
+}
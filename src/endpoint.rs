@@ -0,0 +1,269 @@
+use std::{
+    fmt, io,
+    net::{self, ToSocketAddrs},
+    path,
+};
+
+use crate::config;
+
+/// A connected stream to the server, over TCP, a unix socket or an SSH command.
+/// `protocol::TcpConnection` speaks the protocol over any of them.
+pub(crate) trait Stream: io::Read + io::Write + Send {}
+
+impl<T: io::Read + io::Write + Send> Stream for T {}
+
+/// Where a configured server address connects to.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Endpoint {
+    Tcp(net::SocketAddr),
+    // `unix:/path/to.sock`
+    Unix(path::PathBuf),
+    // `ssh://user@host[:port]/server-host:server-port`
+    Ssh {
+        destination: String,
+        port: Option<u16>,
+        target: String,
+    },
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Tcp(addr) => write!(f, "{}", addr),
+            Endpoint::Unix(socket_path) => write!(f, "unix:{}", socket_path.display()),
+            Endpoint::Ssh {
+                destination,
+                port: Some(port),
+                target,
+            } => write!(f, "ssh://{}:{}/{}", destination, port, target),
+            Endpoint::Ssh {
+                destination,
+                port: None,
+                target,
+            } => write!(f, "ssh://{}/{}", destination, target),
+        }
+    }
+}
+
+impl Endpoint {
+    /// Parses a configured address. TCP addresses resolve to an endpoint per IP address.
+    pub(crate) fn resolve(addr: &str) -> Result<Vec<Endpoint>, Box<dyn std::error::Error>> {
+        if let Some(socket_path) = addr.strip_prefix("unix:") {
+            return Ok(vec![Endpoint::Unix(path::PathBuf::from(socket_path))]);
+        }
+        if let Some(ssh_addr) = addr.strip_prefix("ssh://") {
+            let (authority, target) = ssh_addr.split_once('/').ok_or_else(|| {
+                format!(
+                    "`{}` needs the server address as seen from the SSH host, e.g. ssh://user@host/127.0.0.1:3000",
+                    addr
+                )
+            })?;
+            let host_start = authority.find('@').map_or(0, |at| at + 1);
+            let (destination, port) = match authority[host_start..].rsplit_once(':') {
+                Some((host, port)) => (
+                    format!("{}{}", &authority[..host_start], host),
+                    Some(port.parse()?),
+                ),
+                None => (authority.to_string(), None),
+            };
+            return Ok(vec![Endpoint::Ssh {
+                destination,
+                port,
+                target: target.to_string(),
+            }]);
+        }
+        Ok(addr.to_socket_addrs()?.map(Endpoint::Tcp).collect())
+    }
+
+    /// Connects with the configured timeouts, and keepalive for TCP.
+    pub(crate) fn connect(
+        &self,
+        config: &config::ClientConfig,
+    ) -> Result<Box<dyn Stream>, Box<dyn std::error::Error>> {
+        match self {
+            Endpoint::Tcp(addr) => {
+                let tcp_stream = net::TcpStream::connect_timeout(addr, config.connect_timeout())?;
+                socket2::SockRef::from(&tcp_stream).set_tcp_keepalive(
+                    &socket2::TcpKeepalive::new().with_time(config.keepalive()),
+                )?;
+                tcp_stream.set_read_timeout(Some(config.read_timeout()))?;
+                tcp_stream.set_write_timeout(Some(config.write_timeout()))?;
+                Ok(Box::new(tcp_stream))
+            }
+            #[cfg(unix)]
+            Endpoint::Unix(socket_path) => with_timeouts(config, connect_unix(socket_path)?),
+            #[cfg(unix)]
+            Endpoint::Ssh {
+                destination,
+                port,
+                target,
+            } => with_timeouts(
+                config,
+                connect_ssh(
+                    config.ssh_command(),
+                    destination,
+                    port.unwrap_or(22),
+                    target,
+                )?,
+            ),
+            #[cfg(not(unix))]
+            Endpoint::Unix(_) | Endpoint::Ssh { .. } => {
+                Err(format!("`{}` is not supported on this platform", self).into())
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn with_timeouts(
+    config: &config::ClientConfig,
+    unix_stream: std::os::unix::net::UnixStream,
+) -> Result<Box<dyn Stream>, Box<dyn std::error::Error>> {
+    unix_stream.set_read_timeout(Some(config.read_timeout()))?;
+    unix_stream.set_write_timeout(Some(config.write_timeout()))?;
+    Ok(Box::new(unix_stream))
+}
+
+#[cfg(unix)]
+fn connect_unix(
+    socket_path: &path::Path,
+) -> Result<std::os::unix::net::UnixStream, Box<dyn std::error::Error>> {
+    Ok(std::os::unix::net::UnixStream::connect(socket_path)?)
+}
+
+// Splits `ssh_command` like a shell would, then fills in the placeholders of each argument.
+fn ssh_command_line(
+    ssh_command: &str,
+    destination: &str,
+    port: u16,
+    target: &str,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let command_line: Vec<String> = shell_words::split(ssh_command)?
+        .into_iter()
+        .map(|arg| {
+            arg.replace("{destination}", destination)
+                .replace("{port}", &port.to_string())
+                .replace("{target}", target)
+        })
+        .collect();
+    if command_line.is_empty() {
+        return Err("`ssh_command` is empty".into());
+    }
+    Ok(command_line)
+}
+
+// Spawns `ssh_command` with one end of a socket pair as its stdin and stdout, and speaks the
+// protocol over the other end.
+#[cfg(unix)]
+fn connect_ssh(
+    ssh_command: &str,
+    destination: &str,
+    port: u16,
+    target: &str,
+) -> Result<std::os::unix::net::UnixStream, Box<dyn std::error::Error>> {
+    use std::{
+        os::{fd::OwnedFd, unix::net::UnixStream},
+        process, thread,
+    };
+
+    let command_line = ssh_command_line(ssh_command, destination, port, target)?;
+    log::debug!("Spawning `{}` to reach {}", command_line[0], target);
+
+    let (stream, command_stream) = UnixStream::pair()?;
+    let mut child = process::Command::new(&command_line[0])
+        .args(&command_line[1..])
+        .stdin(OwnedFd::from(command_stream.try_clone()?))
+        .stdout(OwnedFd::from(command_stream))
+        .spawn()?;
+    // Reap the command once it exits, which it does when the stream is closed.
+    thread::spawn(move || child.wait());
+
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use super::*;
+
+    #[test]
+    fn ssh_command_keeps_quoted_arguments_together() {
+        assert_eq!(
+            ssh_command_line(
+                "ssh -o 'ProxyJump=jump host' -p {port} -W {target} {destination}",
+                "user@example.com",
+                2222,
+                "127.0.0.1:3000"
+            )
+            .unwrap(),
+            [
+                "ssh",
+                "-o",
+                "ProxyJump=jump host",
+                "-p",
+                "2222",
+                "-W",
+                "127.0.0.1:3000",
+                "user@example.com"
+            ]
+        );
+        assert!(ssh_command_line("  ", "host", 22, "target").is_err());
+    }
+
+    #[test]
+    fn resolves_unix_and_ssh_addresses() {
+        assert_eq!(
+            Endpoint::resolve("unix:/run/hcs.sock").unwrap(),
+            [Endpoint::Unix("/run/hcs.sock".into())]
+        );
+        assert_eq!(
+            Endpoint::resolve("ssh://user@host:2222/127.0.0.1:3000").unwrap(),
+            [Endpoint::Ssh {
+                destination: "user@host".to_string(),
+                port: Some(2222),
+                target: "127.0.0.1:3000".to_string(),
+            }]
+        );
+        assert!(Endpoint::resolve("ssh://user@host").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket_stream_carries_data_both_ways() {
+        use std::os::unix::net::UnixListener;
+
+        let directory = tempfile::tempdir().unwrap();
+        let socket_path = directory.path().join("server.sock");
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 5];
+            stream.read_exact(&mut request).unwrap();
+            stream.write_all(&request).unwrap();
+        });
+
+        let mut stream = connect_unix(&socket_path).unwrap();
+        stream
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+        stream.write_all(b"hello").unwrap();
+        let mut response = [0; 5];
+        stream.read_exact(&mut response).unwrap();
+        assert_eq!(&response, b"hello");
+        server.join().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn ssh_command_stream_carries_data_both_ways() {
+        let mut stream = connect_ssh("cat", "host", 22, "target").unwrap();
+        stream
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+        stream.write_all(b"hello").unwrap();
+        let mut response = [0; 5];
+        stream.read_exact(&mut response).unwrap();
+        assert_eq!(&response, b"hello");
+    }
+}
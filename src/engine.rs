@@ -0,0 +1,293 @@
+use std::{cell::RefCell, fs, future, sync::Arc};
+
+use hcs_lib::client_database;
+use tokio::{
+    runtime, signal,
+    sync::{mpsc, watch},
+    task, time,
+};
+
+use crate::{
+    changes, config, control, detect, errors, lock, metrics, status, sync_client_to_server,
+    sync_server_to_client, watcher,
+};
+
+pub type AsyncResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+thread_local! {
+    // The cancellation flag of the engine step running on this thread, if any.
+    static CANCELLED: RefCell<Option<Arc<watch::Sender<bool>>>> = const { RefCell::new(None) };
+}
+
+/// Fails with `ClientError::Cancelled` once the engine running the current step was cancelled.
+/// The sync loops call this between changes.
+pub(crate) fn check_cancelled() -> Result<(), Box<dyn std::error::Error>> {
+    let cancelled = CANCELLED.with(|cancelled| {
+        cancelled
+            .borrow()
+            .as_ref()
+            .is_some_and(|cancelled| *cancelled.borrow())
+    });
+    if cancelled {
+        return Err(errors::ClientError::Cancelled.into());
+    }
+    Ok(())
+}
+
+/// Runs the blocking sync steps on tokio's blocking pool, so they can be driven alongside timers,
+/// signals and other tasks. The steps and their connections stay blocking, since the protocol
+/// framing lives in `hcs_lib`; only the orchestration is async. Cancellation takes effect between
+/// changes, after the one in progress.
+pub struct SyncEngine {
+    config: Arc<config::ClientConfig>,
+    cancelled: Arc<watch::Sender<bool>>,
+}
+
+impl SyncEngine {
+    pub fn new(config: &config::ClientConfig) -> Self {
+        Self {
+            config: Arc::new(config.clone()),
+            cancelled: Arc::new(watch::channel(false).0),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.send_replace(true);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.cancelled.borrow()
+    }
+
+    async fn run_blocking<T, F>(&self, step: F) -> AsyncResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&config::ClientConfig) -> Result<T, Box<dyn std::error::Error>> + Send + 'static,
+    {
+        if self.is_cancelled() {
+            return Err(errors::ClientError::Cancelled.into());
+        }
+        let config = Arc::clone(&self.config);
+        let cancelled = Arc::clone(&self.cancelled);
+        task::spawn_blocking(move || {
+            CANCELLED.with(|current| *current.borrow_mut() = Some(cancelled));
+            let result = step(&config).map_err(errors::into_send_error);
+            CANCELLED.with(|current| *current.borrow_mut() = None);
+            result
+        })
+        .await?
+    }
+
+    pub async fn detect(&self) -> AsyncResult<()> {
+        self.run_blocking(detect).await
+    }
+
+    pub async fn sync_up(&self) -> AsyncResult<()> {
+        self.detect().await?;
+        self.run_blocking(sync_client_to_server::sync_client_to_server)
+            .await
+    }
+
+    pub async fn sync_down(&self) -> AsyncResult<()> {
+        self.detect().await?;
+        self.run_blocking(sync_server_to_client::sync_server_to_client)
+            .await
+    }
+
+    pub async fn sync(&self) -> AsyncResult<()> {
+        self.detect().await?;
+        self.run_blocking(sync_server_to_client::sync_server_to_client)
+            .await?;
+        // Conflicts can turn pending changes back into local files to detect.
+        self.detect().await?;
+        self.run_blocking(sync_client_to_server::sync_client_to_server)
+            .await
+    }
+
+    // Only opens sync connections when there is something to sync in that direction.
+    async fn sync_if_needed(&self) -> AsyncResult<()> {
+        self.detect().await?;
+
+        let remote_server_version = self.run_blocking(status::query_server_version).await?;
+        let server_version = self
+            .run_blocking(|config| {
+                Ok(client_database::ServerVersion::init(
+                    &config.file_handler_config().program_data_directory,
+                )
+                .server_version())
+            })
+            .await?;
+        if remote_server_version > server_version {
+            self.run_blocking(sync_server_to_client::sync_server_to_client)
+                .await?;
+            self.detect().await?;
+        }
+
+        let has_pending_changes = self
+            .run_blocking(|config| {
+                Ok(!changes::PendingChanges::read(config.file_handler_config()).is_empty())
+            })
+            .await?;
+        if has_pending_changes {
+            self.run_blocking(sync_client_to_server::sync_client_to_server)
+                .await?;
+        }
+        Ok(())
+    }
+
+    // Holds the instance lock for the round only, so other commands can run between rounds.
+    async fn sync_round(&self, direction: Option<control::SyncDirection>) -> AsyncResult<()> {
+        let _lock = self.run_blocking(lock::InstanceLock::wait).await?;
+        match direction {
+            Some(control::SyncDirection::Up) => self.sync_up().await,
+            Some(control::SyncDirection::Down) => self.sync_down().await,
+            Some(control::SyncDirection::Both) | None => self.sync_if_needed().await,
+        }
+    }
+
+    /// Syncs every `live_interval`, shortly after files change in the storage directory and
+    /// whenever `hcs sync-now` asks over the control socket, until cancelled or interrupted. The
+    /// server is polled on each round rather than pushing its changes. Failed rounds are logged
+    /// and retried on the next tick.
+    pub async fn run_live(&self) -> AsyncResult<()> {
+        let socket_path = control::socket_path(self.config.file_handler_config());
+        let (sync_requests, mut sync_requests_rx) = mpsc::channel(8);
+        let control = Arc::new(control::Control::new(sync_requests));
+        let serve_control = control::listen(&socket_path, Arc::clone(&control))?;
+        let control_server = tokio::spawn(async move {
+            if let Err(err) = serve_control.await {
+                log::error!("Control socket stopped: {}", err);
+            }
+        });
+
+        let mut cancelled = self.cancelled.subscribe();
+        let cancel = Arc::clone(&self.cancelled);
+        let interrupt = tokio::spawn(async move {
+            if signal::ctrl_c().await.is_ok() {
+                log::info!("Interrupted, stopping live mode after the current change");
+                cancel.send_replace(true);
+            }
+        });
+        let metrics_server = self.config.metrics_listen().map(|listen| {
+            let serve = metrics::serve(
+                listen.to_string(),
+                self.config.file_handler_config().clone(),
+            );
+            tokio::spawn(async move {
+                if let Err(err) = serve.await {
+                    log::error!("Metrics server stopped: {}", err);
+                }
+            })
+        });
+        let mut storage_watcher =
+            match watcher::StorageWatcher::start(self.config.file_handler_config()) {
+                Ok(storage_watcher) => Some(storage_watcher),
+                Err(err) => {
+                    log::warn!(
+                        "Can't watch the storage directory, syncing on the interval only: {}",
+                        err
+                    );
+                    None
+                }
+            };
+        let mut interval = time::interval(self.config.live_interval());
+        interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+
+        log::info!(
+            "Live mode started, syncing every {}s",
+            self.config.live_interval().as_secs()
+        );
+        loop {
+            let sync_request = tokio::select! {
+                _ = interval.tick() => {
+                    if control.is_paused() {
+                        continue;
+                    }
+                    None
+                }
+                _ = storage_changed(&mut storage_watcher) => {
+                    if control.is_paused() {
+                        continue;
+                    }
+                    log::debug!("Local files changed");
+                    None
+                }
+                Some(sync_request) = sync_requests_rx.recv() => Some(sync_request),
+                _ = cancelled.wait_for(|cancelled| *cancelled) => None,
+            };
+            if self.is_cancelled() {
+                break;
+            }
+
+            control.sync_started();
+            let result = self
+                .sync_round(sync_request.as_ref().map(|(direction, _)| *direction))
+                .await;
+            // Changes made while syncing are picked up by the next tick.
+            if let Some(storage_watcher) = &mut storage_watcher {
+                storage_watcher.clear();
+            }
+            control.sync_finished(result.as_ref().err().map(|err| err.to_string()));
+            if let Some((_, reply)) = sync_request {
+                let _ = reply.send(match &result {
+                    Ok(()) => control::Response::Done,
+                    Err(err) => control::Response::Failed(err.to_string()),
+                });
+            }
+            match result {
+                Ok(()) => metrics::record_successful_sync(),
+                Err(err) if err.downcast_ref() == Some(&errors::ClientError::Cancelled) => break,
+                Err(err) => {
+                    metrics::record_error(&*err);
+                    log::error!("Live sync failed: {}", err);
+                }
+            }
+        }
+
+        interrupt.abort();
+        control_server.abort();
+        let _ = fs::remove_file(&socket_path);
+        if let Some(metrics_server) = metrics_server {
+            metrics_server.abort();
+        }
+        log::info!("Live mode stopped");
+        Ok(())
+    }
+}
+
+async fn storage_changed(storage_watcher: &mut Option<watcher::StorageWatcher>) {
+    match storage_watcher {
+        Some(storage_watcher) => storage_watcher.changed().await,
+        None => future::pending().await,
+    }
+}
+
+/// Runs an engine future to completion from blocking code, such as the CLI commands.
+pub fn block_on<T>(
+    future: impl future::Future<Output = AsyncResult<T>>,
+) -> Result<T, Box<dyn std::error::Error>> {
+    let runtime = runtime::Builder::new_multi_thread().enable_all().build()?;
+    runtime
+        .block_on(future)
+        .map_err(|err| err as Box<dyn std::error::Error>)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancelling_fails_the_running_step_at_its_next_check() {
+        assert!(check_cancelled().is_ok());
+
+        let cancelled = Arc::new(watch::channel(false).0);
+        CANCELLED.with(|current| *current.borrow_mut() = Some(Arc::clone(&cancelled)));
+        assert!(check_cancelled().is_ok());
+        cancelled.send_replace(true);
+        assert_eq!(
+            check_cancelled().unwrap_err().downcast_ref(),
+            Some(&errors::ClientError::Cancelled)
+        );
+        CANCELLED.with(|current| *current.borrow_mut() = None);
+    }
+}
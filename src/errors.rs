@@ -1,6 +1,80 @@
+use std::{fmt, io};
+
 use hcs_lib::data;
 
 #[derive(Debug, serde::Serialize, serde::Deserialize, PartialEq)]
-pub enum ServerTcpError {}
+pub enum ServerTcpError {
+    PathNotFound(String),
+}
 
 impl data::Data for ServerTcpError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientError {
+    ServerAhead,
+    Cancelled,
+    // Another process holds the instance lock, with its pid if known.
+    AlreadyRunning(Option<u32>),
+    // The server acknowledged a different change than the oldest one in flight.
+    OutOfSequence { expected: u64, received: u64 },
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::ServerAhead => write!(
+                f,
+                "Server responded with ServerVersion. You must first sync the server to the client."
+            ),
+            ClientError::Cancelled => write!(f, "Sync was cancelled."),
+            ClientError::AlreadyRunning(Some(pid)) => {
+                write!(f, "Another hcs is running (pid {}).", pid)
+            }
+            ClientError::AlreadyRunning(None) => write!(f, "Another hcs is running."),
+            ClientError::OutOfSequence { expected, received } => write!(
+                f,
+                "Server acknowledged change {} while change {} was expected.",
+                received, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+/// Keeps `ClientError`s and I/O errors recognisable after they cross a thread boundary.
+pub fn into_send_error(
+    err: Box<dyn std::error::Error>,
+) -> Box<dyn std::error::Error + Send + Sync> {
+    let err = match err.downcast::<ClientError>() {
+        Ok(client_error) => return client_error,
+        Err(err) => err,
+    };
+    match err.downcast::<io::Error>() {
+        Ok(io_error) => io_error,
+        Err(err) => err.to_string().into(),
+    }
+}
+
+/// Whether `err` comes from a refused, dropped or timed out connection, which is worth
+/// reconnecting for.
+pub fn is_connection_error(err: &(dyn std::error::Error + 'static)) -> bool {
+    let mut source = Some(err);
+    while let Some(err) = source {
+        if let Some(io_error) = err.downcast_ref::<io::Error>() {
+            return matches!(
+                io_error.kind(),
+                io::ErrorKind::TimedOut
+                    | io::ErrorKind::WouldBlock
+                    | io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::NotConnected
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::UnexpectedEof
+            );
+        }
+        source = err.source();
+    }
+    false
+}
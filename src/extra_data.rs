@@ -1,6 +1,45 @@
 use hcs_lib::data;
 
+/// Requests beyond the sync protocol of `hcs_lib`. Servers that predate them answer with an
+/// error or close the connection, which the commands using them report as such.
 #[derive(Debug, serde::Serialize, serde::Deserialize, PartialEq)]
-pub enum ExtraData {}
+pub enum ExtraData {
+    // Asks the server for its current version, answered with `Transmission::ServerVersion`.
+    ServerVersionQuery,
+    // Asks the server which events a client at `server_version` would receive, without their
+    // file contents. Answered with `PendingEvents`.
+    PendingEventsQuery {
+        server_version: i32,
+    },
+    PendingEvents(Vec<data::ChangeEvent>),
+    // Asks the server for the entries of a directory, answered with `RemoteListing`.
+    ListRemote {
+        path: String,
+    },
+    RemoteListing(Vec<RemoteEntry>),
+    // Asks the server for a single file, answered with a `FileCreate` change event followed by
+    // the file contents.
+    FetchFile {
+        path: String,
+    },
+    // A change of a pipelined `SyncClientToServer` transaction, numbered from 0. Answered with an
+    // `Acknowledgement` carrying the same sequence number once the server applied it.
+    SequencedChange {
+        sequence: u64,
+        change_event: data::ChangeEvent,
+    },
+    Acknowledgement {
+        sequence: u64,
+        server_version: i32,
+    },
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq)]
+pub struct RemoteEntry {
+    pub path: String,
+    pub is_directory: bool,
+    pub size: u64,
+    pub last_modified: i64,
+}
 
 impl data::Data for ExtraData {}
@@ -0,0 +1,113 @@
+use std::{fs, path};
+
+use hcs_lib::client_database;
+
+use crate::{config, endpoint};
+
+fn last_good_path(file_handler_config: &client_database::FileHandlerConfig) -> path::PathBuf {
+    file_handler_config
+        .program_data_directory
+        .join("last_server")
+}
+
+fn last_good(file_handler_config: &client_database::FileHandlerConfig) -> Option<String> {
+    fs::read_to_string(last_good_path(file_handler_config))
+        .ok()
+        .map(|endpoint| endpoint.trim().to_string())
+}
+
+/// Every endpoint the configured servers resolve to, in configuration order, with the last server
+/// that accepted a connection moved to the front.
+pub(crate) fn candidates(config: &config::ClientConfig) -> Vec<endpoint::Endpoint> {
+    ordered_candidates(
+        &config.tcp_addrs(),
+        last_good(config.file_handler_config()).as_deref(),
+    )
+}
+
+fn ordered_candidates(addrs: &[&str], last_good: Option<&str>) -> Vec<endpoint::Endpoint> {
+    let mut candidates: Vec<endpoint::Endpoint> = Vec::new();
+    for addr in addrs {
+        match endpoint::Endpoint::resolve(addr) {
+            Ok(resolved) => {
+                for endpoint in resolved {
+                    if !candidates.contains(&endpoint) {
+                        candidates.push(endpoint);
+                    }
+                }
+            }
+            Err(err) => log::warn!("Failed to resolve `{}`: {}", addr, err),
+        }
+    }
+
+    if let Some(last_good) = last_good {
+        if let Some(position) = candidates
+            .iter()
+            .position(|endpoint| endpoint.to_string() == last_good)
+        {
+            let last_good = candidates.remove(position);
+            candidates.insert(0, last_good);
+        }
+    }
+    candidates
+}
+
+pub(crate) fn remember(config: &config::ClientConfig, endpoint: &endpoint::Endpoint) {
+    let file_handler_config = config.file_handler_config();
+    let endpoint = endpoint.to_string();
+    if last_good(file_handler_config).as_ref() == Some(&endpoint) {
+        return;
+    }
+    log::info!("Using server {}", endpoint);
+    if let Err(err) = fs::write(last_good_path(file_handler_config), &endpoint) {
+        log::warn!("Failed to remember server {}: {}", endpoint, err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn displayed(candidates: Vec<endpoint::Endpoint>) -> Vec<String> {
+        candidates
+            .iter()
+            .map(|endpoint| endpoint.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn candidates_keep_configuration_order() {
+        assert_eq!(
+            displayed(ordered_candidates(
+                &["127.0.0.1:3000", "unix:/run/hcs.sock", "127.0.0.1:3000"],
+                None
+            )),
+            ["127.0.0.1:3000", "unix:/run/hcs.sock"]
+        );
+    }
+
+    #[test]
+    fn last_good_server_is_tried_first() {
+        let addrs = ["127.0.0.1:3000", "127.0.0.2:3000", "unix:/run/hcs.sock"];
+        assert_eq!(
+            displayed(ordered_candidates(&addrs, Some("127.0.0.2:3000"))),
+            ["127.0.0.2:3000", "127.0.0.1:3000", "unix:/run/hcs.sock"]
+        );
+        // A server no longer configured is ignored.
+        assert_eq!(
+            displayed(ordered_candidates(&addrs, Some("127.0.0.9:3000"))),
+            ["127.0.0.1:3000", "127.0.0.2:3000", "unix:/run/hcs.sock"]
+        );
+    }
+
+    #[test]
+    fn unresolvable_addresses_are_skipped() {
+        assert_eq!(
+            displayed(ordered_candidates(
+                &["not an address", "127.0.0.1:3000"],
+                None
+            )),
+            ["127.0.0.1:3000"]
+        );
+    }
+}
@@ -0,0 +1,262 @@
+use std::{
+    fs,
+    io::{BufRead, BufReader, Write},
+    path,
+};
+
+use chrono::TimeZone;
+use hcs_lib::{client_database, data};
+
+use crate::{changes, config, rate_limit};
+
+// The history file is rotated once it grows past this size.
+const MAX_HISTORY_BYTES: u64 = 10 * 1024 * 1024;
+// Rotated files kept besides the current one, e.g. `history.1.jsonl` to `history.4.jsonl`.
+const ROTATED_HISTORY_FILES: usize = 4;
+
+/// One change applied by a sync, as stored in `program_data_directory/history.jsonl`.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct HistoryEntry {
+    pub timestamp: i64,
+    pub direction: rate_limit::Direction,
+    pub event_kind: String,
+    pub paths: Vec<path::PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_version: Option<i32>,
+    pub outcome: String,
+}
+
+impl HistoryEntry {
+    pub fn new(
+        direction: rate_limit::Direction,
+        change_event: &data::ChangeEvent,
+        outcome: &str,
+    ) -> Self {
+        Self {
+            timestamp: chrono::Local::now().timestamp(),
+            direction,
+            event_kind: changes::change_event_kind(change_event).to_string(),
+            paths: changes::change_event_paths(change_event),
+            size: changes::change_event_size(change_event),
+            server_version: None,
+            outcome: outcome.to_string(),
+        }
+    }
+
+    fn concerns(&self, filter_path: &path::Path) -> bool {
+        self.paths.iter().any(|path| path.starts_with(filter_path))
+    }
+}
+
+fn history_path(
+    file_handler_config: &client_database::FileHandlerConfig,
+    rotation: usize,
+) -> path::PathBuf {
+    let file_name = match rotation {
+        0 => "history.jsonl".to_string(),
+        rotation => format!("history.{}.jsonl", rotation),
+    };
+    file_handler_config.program_data_directory.join(file_name)
+}
+
+fn rotate(
+    file_handler_config: &client_database::FileHandlerConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let oldest = history_path(file_handler_config, ROTATED_HISTORY_FILES);
+    if oldest.exists() {
+        fs::remove_file(oldest)?;
+    }
+    for rotation in (0..ROTATED_HISTORY_FILES).rev() {
+        let from = history_path(file_handler_config, rotation);
+        if from.exists() {
+            fs::rename(from, history_path(file_handler_config, rotation + 1))?;
+        }
+    }
+    Ok(())
+}
+
+/// Appends `entry` to the history, rotating the file when it gets too large.
+pub fn record(
+    file_handler_config: &client_database::FileHandlerConfig,
+    entry: &HistoryEntry,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let current = history_path(file_handler_config, 0);
+    if fs::metadata(&current).is_ok_and(|metadata| metadata.len() >= MAX_HISTORY_BYTES) {
+        rotate(file_handler_config)?;
+    }
+
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+    // A single appended write keeps each line whole.
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(current)?
+        .write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Every recorded entry, oldest first.
+pub fn read(
+    file_handler_config: &client_database::FileHandlerConfig,
+) -> Result<Vec<HistoryEntry>, Box<dyn std::error::Error>> {
+    let mut entries = Vec::new();
+    for rotation in (0..=ROTATED_HISTORY_FILES).rev() {
+        let file = match fs::File::open(history_path(file_handler_config, rotation)) {
+            Ok(file) => file,
+            Err(_) => continue,
+        };
+        for line in BufReader::new(file).lines() {
+            match serde_json::from_str(&line?) {
+                Ok(entry) => entries.push(entry),
+                Err(err) => log::warn!("Skipping unreadable history entry: {}", err),
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Parses `--since`: a date, a date and time, or a duration ago such as `30m`, `12h` or `7d`.
+fn parse_since(since: &str) -> Result<i64, Box<dyn std::error::Error>> {
+    let now = chrono::Local::now();
+    if let Some(unit) = since
+        .chars()
+        .last()
+        .filter(|unit| unit.is_ascii_alphabetic())
+    {
+        if let Ok(amount) = since[..since.len() - 1].parse::<i64>() {
+            let ago = match unit {
+                's' => chrono::Duration::seconds(amount),
+                'm' => chrono::Duration::minutes(amount),
+                'h' => chrono::Duration::hours(amount),
+                'd' => chrono::Duration::days(amount),
+                'w' => chrono::Duration::weeks(amount),
+                _ => return Err(format!("Unknown unit in `--since {}`", since).into()),
+            };
+            return Ok((now - ago).timestamp());
+        }
+    }
+
+    let naive = chrono::NaiveDateTime::parse_from_str(since, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| chrono::NaiveDateTime::parse_from_str(since, "%Y-%m-%d %H:%M"))
+        .or_else(|_| {
+            chrono::NaiveDate::parse_from_str(since, "%Y-%m-%d")
+                .map(|date| date.and_hms_opt(0, 0, 0).unwrap_or_default())
+        })
+        .map_err(|_| {
+            format!(
+                "Invalid `--since {}`, expected e.g. 2023-05-07, \"2023-05-07 14:30\" or 12h",
+                since
+            )
+        })?;
+    chrono::Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(|since| since.timestamp())
+        .ok_or_else(|| format!("`{}` does not exist in the local time zone", since).into())
+}
+
+pub fn log(
+    config: &config::ClientConfig,
+    filter_path: Option<&path::Path>,
+    since: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let since = since.map(parse_since).transpose()?;
+    let entries: Vec<HistoryEntry> = read(config.file_handler_config())?
+        .into_iter()
+        .filter(|entry| since.is_none_or(|since| entry.timestamp >= since))
+        .filter(|entry| filter_path.is_none_or(|filter_path| entry.concerns(filter_path)))
+        .collect();
+    if entries.is_empty() {
+        println!("No history.");
+        return Ok(());
+    }
+
+    for entry in entries {
+        let timestamp = chrono::Local
+            .timestamp_opt(entry.timestamp, 0)
+            .single()
+            .map(|timestamp| timestamp.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default();
+        let direction = match entry.direction {
+            rate_limit::Direction::Upload => "up",
+            rate_limit::Direction::Download => "down",
+        };
+        let paths = entry
+            .paths
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(" -> ");
+        let server_version = entry
+            .server_version
+            .map(|server_version| format!("v{}", server_version))
+            .unwrap_or_else(|| "-".to_string());
+        println!(
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            timestamp, direction, server_version, entry.event_kind, paths, entry.outcome
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(timestamp: i64, path: &str) -> HistoryEntry {
+        HistoryEntry {
+            timestamp,
+            direction: rate_limit::Direction::Upload,
+            event_kind: "file create".to_string(),
+            paths: vec![path::PathBuf::from(path)],
+            size: Some(3),
+            server_version: Some(7),
+            outcome: "applied".to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_durations_ago_and_dates() {
+        let now = chrono::Local::now().timestamp();
+        let twelve_hours_ago = parse_since("12h").unwrap();
+        assert!((now - 12 * 3600 - twelve_hours_ago).abs() <= 1);
+        assert!((now - 7 * 24 * 3600 - parse_since("7d").unwrap()).abs() <= 1);
+
+        let date = parse_since("2023-05-07").unwrap();
+        assert_eq!(parse_since("2023-05-07 00:00").unwrap(), date);
+        assert_eq!(
+            parse_since("2023-05-07 14:30:00").unwrap(),
+            date + 14 * 3600 + 30 * 60
+        );
+
+        assert!(parse_since("3y").is_err());
+        assert!(parse_since("yesterday").is_err());
+    }
+
+    #[test]
+    fn rotated_history_is_read_oldest_first() {
+        let directory = tempfile::tempdir().unwrap();
+        let file_handler_config = client_database::FileHandlerConfig {
+            storage_directory: directory.path().join("storage"),
+            symlink_directory: directory.path().join("symlink"),
+            temporary_directory: directory.path().join("temporary"),
+            program_data_directory: directory.path().to_path_buf(),
+        };
+
+        for timestamp in 0..=ROTATED_HISTORY_FILES as i64 + 1 {
+            record(&file_handler_config, &entry(timestamp, "a.txt")).unwrap();
+            rotate(&file_handler_config).unwrap();
+        }
+        record(&file_handler_config, &entry(100, "dir/b.txt")).unwrap();
+
+        // The oldest rotations fell off the end.
+        let entries = read(&file_handler_config).unwrap();
+        let timestamps: Vec<i64> = entries.iter().map(|entry| entry.timestamp).collect();
+        assert_eq!(timestamps, [2, 3, 4, 5, 100]);
+        assert!(entries[4].concerns(path::Path::new("dir")));
+        assert!(!entries[4].concerns(path::Path::new("a.txt")));
+    }
+}
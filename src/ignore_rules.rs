@@ -0,0 +1,121 @@
+use std::{collections::HashMap, fs, path};
+
+use hcs_lib::{client_database, data};
+use ignore::gitignore;
+
+use crate::{changes, sync_server_to_client};
+
+const IGNORE_FILE_NAME: &str = ".hcsignore";
+
+/// Gitignore style rules read from `.hcsignore` files in the storage directory and from the
+/// global `.hcsignore` in `program_data_directory`.
+pub struct IgnoreRules {
+    storage_directory: path::PathBuf,
+    global: gitignore::Gitignore,
+    directories: HashMap<path::PathBuf, gitignore::Gitignore>,
+}
+
+fn read_ignore_files(
+    directory: &path::Path,
+    directories: &mut HashMap<path::PathBuf, gitignore::Gitignore>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let ignore_file = directory.join(IGNORE_FILE_NAME);
+    if ignore_file.is_file() {
+        let (ignore, err) = gitignore::Gitignore::new(&ignore_file);
+        if let Some(err) = err {
+            log::warn!("Invalid rule in `{}`: {}", ignore_file.display(), err);
+        }
+        directories.insert(directory.to_path_buf(), ignore);
+    }
+
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            read_ignore_files(&entry.path(), directories)?;
+        }
+    }
+    Ok(())
+}
+
+impl IgnoreRules {
+    pub fn init(
+        file_handler_config: &client_database::FileHandlerConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let storage_directory = file_handler_config.storage_directory.clone();
+
+        let mut builder = gitignore::GitignoreBuilder::new(&storage_directory);
+        // Left behind by a download that was interrupted.
+        builder.add_line(None, &format!("*{}", sync_server_to_client::PARTIAL_SUFFIX))?;
+        let global_ignore_file = file_handler_config
+            .program_data_directory
+            .join(IGNORE_FILE_NAME);
+        if global_ignore_file.is_file() {
+            if let Some(err) = builder.add(&global_ignore_file) {
+                log::warn!(
+                    "Invalid rule in `{}`: {}",
+                    global_ignore_file.display(),
+                    err
+                );
+            }
+        }
+        let global = builder.build()?;
+
+        let mut directories = HashMap::new();
+        read_ignore_files(&storage_directory, &mut directories)?;
+
+        Ok(Self {
+            storage_directory,
+            global,
+            directories,
+        })
+    }
+
+    pub fn is_ignored(&self, relative_path: &path::Path, is_dir: bool) -> bool {
+        let absolute_path = self.storage_directory.join(relative_path);
+
+        // The closest `.hcsignore` wins, falling back to the global rules.
+        for directory in absolute_path.ancestors().skip(1) {
+            if !directory.starts_with(&self.storage_directory) {
+                break;
+            }
+            if let Some(ignore) = self.directories.get(directory) {
+                match ignore.matched_path_or_any_parents(&absolute_path, is_dir) {
+                    ignore::Match::Ignore(_) => return true,
+                    ignore::Match::Whitelist(_) => return false,
+                    ignore::Match::None => {}
+                }
+            }
+        }
+
+        self.global
+            .matched_path_or_any_parents(&absolute_path, is_dir)
+            .is_ignore()
+    }
+
+    /// An event is ignored when every path it touches is ignored.
+    pub fn is_ignored_event(&self, change_event: &data::ChangeEvent) -> bool {
+        let is_dir = matches!(change_event, data::ChangeEvent::Directory(_));
+        let paths = changes::change_event_paths(change_event);
+        !paths.is_empty() && paths.iter().all(|path| self.is_ignored(path, is_dir))
+    }
+
+    /// Deletes change files for ignored paths, so they are never synced up.
+    pub fn discard_ignored_changes(
+        &self,
+        file_handler_config: &client_database::FileHandlerConfig,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        for (change_id, change_event) in client_database::read_changes(file_handler_config) {
+            if self.is_ignored_event(&change_event) {
+                log::debug!(
+                    "Discarding ignored {}",
+                    changes::change_event_kind(&change_event)
+                );
+                let change_file = changes::change_file_path(file_handler_config, change_id);
+                if change_file.exists() {
+                    fs::remove_file(change_file)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,11 +1,36 @@
-use hcs_lib::data;
+use hcs_lib::{client_detect_offline, data, protocol};
 
 pub mod args;
+pub mod changes;
 pub mod config;
+pub mod conflicts;
+pub mod control;
+pub mod diff;
+pub mod dry_run;
+mod endpoint;
+pub mod engine;
 pub mod errors;
 pub mod extra_data;
+pub mod failover;
+pub mod history;
+pub mod ignore_rules;
+pub mod lock;
+pub mod logging;
+pub mod merge;
+pub mod metadata;
+pub mod metrics;
+pub mod pins;
+pub mod placeholders;
+pub mod progress;
+pub mod quota;
+pub mod rate_limit;
+pub mod reconnect;
+pub mod remote;
+pub mod selective;
+pub mod status;
 pub mod sync_client_to_server;
 pub mod sync_server_to_client;
+mod watcher;
 
 fn bytes_to_transmission_type(
     bytes: &[u8],
@@ -24,3 +49,67 @@ pub fn transmission_type_to_bytes(
     let bytes = bincode::serialize(&transmission)?;
     Ok(bytes)
 }
+
+/// Connects to the first reachable server with the configured timeouts. The last server that
+/// accepted a connection is tried first.
+fn connect(
+    config: &config::ClientConfig,
+) -> Result<Box<dyn endpoint::Stream>, Box<dyn std::error::Error>> {
+    let mut last_err = None;
+    for endpoint in failover::candidates(config) {
+        match endpoint.connect(config) {
+            Ok(stream) => {
+                failover::remember(config, &endpoint);
+                return Ok(stream);
+            }
+            Err(err) => {
+                log::warn!("Failed to connect to {}: {}", endpoint, err);
+                last_err = Some(err);
+            }
+        }
+    }
+    Err(last_err.unwrap_or_else(|| "No server address in `tcp_config` could be resolved".into()))
+}
+
+/// Connects to the server and performs the greeting, ready for the next request.
+fn open_connection(
+    config: &config::ClientConfig,
+) -> Result<Box<protocol::TcpConnection>, Box<dyn std::error::Error>> {
+    let mut tcp_connection = protocol::TcpConnection::new(connect(config)?);
+
+    {
+        log::debug!("Sending greeting");
+        let greeting = data::Greeting::new("HCS CLIENT".to_string());
+        let transmission = data::Transmission::Greeting(greeting);
+        let bytes = transmission_type_to_bytes(transmission)?;
+        tcp_connection.write(&bytes)?;
+    }
+
+    {
+        log::debug!("Waiting for server to respond with proceed");
+        let response = tcp_connection.read_next_chunk()?;
+        let transmission = bytes_to_transmission_type(&response)?;
+        if transmission != data::Transmission::Proceed {
+            log::error!("Server did not respond with proceed");
+            return Err("Server did not respond with proceed".into());
+        }
+    }
+
+    Ok(tcp_connection)
+}
+
+/// Records offline changes, dropping the ones that must never be synced up before they reach
+/// `changes/`.
+pub fn detect(config: &config::ClientConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let staging = changes::Staging::open(config.file_handler_config())?;
+    client_detect_offline::detect_offline_changes(staging.file_handler_config());
+    placeholders::discard_placeholder_deletions(staging.file_handler_config())?;
+    ignore_rules::IgnoreRules::init(config.file_handler_config())?
+        .discard_ignored_changes(staging.file_handler_config())?;
+    let selective_sync = selective::SelectiveSync::init(config)?;
+    selective_sync.discard_excluded_changes(staging.file_handler_config())?;
+    staging.commit()?;
+    // Exclusions added to `Config.toml` free the local copy like `hcs selective remove` does,
+    // once nothing within it is left to sync.
+    selective_sync.free_excluded(config.file_handler_config())
+}
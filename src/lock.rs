@@ -0,0 +1,138 @@
+use std::{
+    fs,
+    io::{Read, Seek, Write},
+    path, process,
+};
+
+use crate::{config, errors};
+
+/// Keeps other `hcs` processes away from `ServerVersion`, `changes/` and the storage tree while
+/// held. The lock is advisory and the OS drops it when its process exits, so a lock file left
+/// behind by a crashed process is stale and simply taken over. `hcs live` only holds it for each
+/// sync round, so other commands can run between rounds.
+pub struct InstanceLock {
+    file: fs::File,
+}
+
+impl InstanceLock {
+    pub fn acquire(config: &config::ClientConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::acquire_in(&config.file_handler_config().program_data_directory, false)
+    }
+
+    /// Like `acquire`, but waits for the current holder to release the lock.
+    pub fn wait(config: &config::ClientConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::acquire_in(&config.file_handler_config().program_data_directory, true)
+    }
+
+    fn acquire_in(
+        program_data_directory: &path::Path,
+        wait: bool,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        fs::create_dir_all(program_data_directory)?;
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(program_data_directory.join("hcs.lock"))?;
+
+        // The file holds the pid of the process holding the lock, if any.
+        let mut pid = String::new();
+        let locked = if wait {
+            file.lock().map_err(fs::TryLockError::Error)
+        } else {
+            file.try_lock()
+        };
+        match locked {
+            Ok(()) => {}
+            Err(fs::TryLockError::WouldBlock) => {
+                let _ = file.read_to_string(&mut pid);
+                return Err(errors::ClientError::AlreadyRunning(pid.trim().parse().ok()).into());
+            }
+            Err(fs::TryLockError::Error(err)) => return Err(err.into()),
+        }
+        file.read_to_string(&mut pid)?;
+        if let Ok(pid) = pid.trim().parse::<u32>() {
+            log::debug!("Taking over the stale lock of pid {}", pid);
+        }
+
+        file.set_len(0)?;
+        file.rewind()?;
+        write!(file, "{}", process::id())?;
+        file.flush()?;
+        Ok(Self { file })
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        // Closing the file releases the lock. The file itself stays, as removing it could let
+        // two processes lock different files.
+        let _ = self.file.set_len(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn second_lock_reports_the_holder() {
+        let directory = tempfile::tempdir().unwrap();
+        let lock = InstanceLock::acquire_in(directory.path(), false).unwrap();
+        assert_eq!(
+            fs::read_to_string(directory.path().join("hcs.lock")).unwrap(),
+            process::id().to_string()
+        );
+
+        let err = InstanceLock::acquire_in(directory.path(), false)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&errors::ClientError::AlreadyRunning(Some(process::id())))
+        );
+
+        drop(lock);
+        assert_eq!(
+            fs::read_to_string(directory.path().join("hcs.lock")).unwrap(),
+            ""
+        );
+        InstanceLock::acquire_in(directory.path(), false).unwrap();
+    }
+
+    #[test]
+    fn waiting_lock_is_acquired_once_released() {
+        let directory = tempfile::tempdir().unwrap();
+        let lock = InstanceLock::acquire_in(directory.path(), false).unwrap();
+
+        let (acquired, acquired_rx) = std::sync::mpsc::channel();
+        let program_data_directory = directory.path().to_path_buf();
+        let waiter = std::thread::spawn(move || {
+            let lock = InstanceLock::acquire_in(&program_data_directory, true);
+            acquired.send(()).unwrap();
+            lock.map(|_| ()).map_err(|err| err.to_string())
+        });
+        assert!(acquired_rx
+            .recv_timeout(std::time::Duration::from_millis(200))
+            .is_err());
+
+        drop(lock);
+        acquired_rx
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap();
+        waiter.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn stale_lock_file_is_taken_over() {
+        let directory = tempfile::tempdir().unwrap();
+        // Left behind by a process that no longer holds the lock.
+        fs::write(directory.path().join("hcs.lock"), "4294967295").unwrap();
+        let _lock = InstanceLock::acquire_in(directory.path(), false).unwrap();
+        assert_eq!(
+            fs::read_to_string(directory.path().join("hcs.lock")).unwrap(),
+            process::id().to_string()
+        );
+    }
+}
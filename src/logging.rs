@@ -0,0 +1,143 @@
+use std::io::{self, Write};
+
+use hcs_lib::logger;
+
+use crate::config;
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+pub fn init_logger(config: &config::ClientConfig) {
+    match config.log_format() {
+        LogFormat::Text => logger::init_logger(config.log_level()),
+        LogFormat::Json => {
+            let json_logger = JsonLogger {
+                level: config.log_level(),
+            };
+            if log::set_boxed_logger(Box::new(json_logger)).is_ok() {
+                log::set_max_level(config.log_level());
+            }
+        }
+    }
+}
+
+/// Writes every record to stderr as one JSON object per line, with the record's key-values
+/// (e.g. `event_kind`, `path`, `size`, `server_version`, `duration_ms`, and the transfer progress)
+/// as fields.
+struct JsonLogger {
+    level: log::LevelFilter,
+}
+
+struct Fields<'a>(&'a mut serde_json::Map<String, serde_json::Value>);
+
+impl<'kvs> log::kv::VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(
+        &mut self,
+        key: log::kv::Key<'kvs>,
+        value: log::kv::Value<'kvs>,
+    ) -> Result<(), log::kv::Error> {
+        let mut field = Field(serde_json::Value::Null);
+        value.visit(&mut field)?;
+        self.0.insert(key.to_string(), field.0);
+        Ok(())
+    }
+}
+
+// One key-value as JSON. A `None` becomes `null`, and anything that is not a primitive its
+// `Display` string.
+struct Field(serde_json::Value);
+
+impl<'v> log::kv::VisitValue<'v> for Field {
+    fn visit_any(&mut self, value: log::kv::Value) -> Result<(), log::kv::Error> {
+        self.0 = value.to_string().into();
+        Ok(())
+    }
+
+    fn visit_null(&mut self) -> Result<(), log::kv::Error> {
+        self.0 = serde_json::Value::Null;
+        Ok(())
+    }
+
+    fn visit_u64(&mut self, value: u64) -> Result<(), log::kv::Error> {
+        self.0 = value.into();
+        Ok(())
+    }
+
+    fn visit_i64(&mut self, value: i64) -> Result<(), log::kv::Error> {
+        self.0 = value.into();
+        Ok(())
+    }
+
+    fn visit_f64(&mut self, value: f64) -> Result<(), log::kv::Error> {
+        self.0 = value.into();
+        Ok(())
+    }
+
+    fn visit_bool(&mut self, value: bool) -> Result<(), log::kv::Error> {
+        self.0 = value.into();
+        Ok(())
+    }
+
+    fn visit_str(&mut self, value: &str) -> Result<(), log::kv::Error> {
+        self.0 = value.into();
+        Ok(())
+    }
+}
+
+impl log::Log for JsonLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let mut fields = serde_json::Map::new();
+        fields.insert(
+            "timestamp".to_string(),
+            chrono::Local::now().to_rfc3339().into(),
+        );
+        fields.insert("level".to_string(), record.level().as_str().into());
+        fields.insert("target".to_string(), record.target().into());
+        fields.insert("message".to_string(), record.args().to_string().into());
+        let _ = record.key_values().visit(&mut Fields(&mut fields));
+
+        let _ = writeln!(io::stderr(), "{}", serde_json::Value::Object(fields));
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use log::kv::ToValue;
+
+    use super::*;
+
+    #[test]
+    fn key_values_keep_their_json_types() {
+        let eta_secs: Option<u64> = None;
+        let key_values = [
+            ("size", 5u64.to_value()),
+            ("path", "a.txt".to_value()),
+            ("eta_secs", eta_secs.to_value()),
+            ("retry", true.to_value()),
+        ];
+        let record = log::Record::builder().key_values(&key_values).build();
+        let mut fields = serde_json::Map::new();
+        record.key_values().visit(&mut Fields(&mut fields)).unwrap();
+        assert_eq!(
+            serde_json::Value::Object(fields),
+            serde_json::json!({"size": 5, "path": "a.txt", "eta_secs": null, "retry": true})
+        );
+    }
+}
@@ -1,11 +1,15 @@
-use hcs_client::{args, config};
-use hcs_lib::logger;
+use std::process;
+
+use hcs_client::{args, config, logging};
 
 fn main() {
     let config: config::ClientConfig =
         hcs_lib::config::read_config("Config.toml").expect("Failed to read config file");
 
-    logger::init_logger(config.log_level());
+    logging::init_logger(&config);
 
-    args::run_from_args(&config).unwrap();
+    if let Err(err) = args::run_from_args(&config) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
 }
@@ -0,0 +1,322 @@
+use std::{collections::HashMap, fs, path};
+
+use hcs_lib::{client_database, data};
+use sha2::Digest;
+
+use crate::diff;
+
+struct Hunk<'a> {
+    start: usize,
+    end: usize,
+    lines: Vec<&'a str>,
+}
+
+fn hunks<'a>(base: &[&'a str], other: &[&'a str]) -> Vec<Hunk<'a>> {
+    let mut hunks = Vec::new();
+    let mut current: Option<Hunk> = None;
+    let mut base_index = 0;
+    for op in diff::diff_lines(base, other) {
+        match op {
+            diff::DiffOp::Equal(_) => {
+                hunks.extend(current.take());
+                base_index += 1;
+            }
+            diff::DiffOp::Delete(_) => {
+                current
+                    .get_or_insert(Hunk {
+                        start: base_index,
+                        end: base_index,
+                        lines: Vec::new(),
+                    })
+                    .end += 1;
+                base_index += 1;
+            }
+            diff::DiffOp::Insert(line) => {
+                current
+                    .get_or_insert(Hunk {
+                        start: base_index,
+                        end: base_index,
+                        lines: Vec::new(),
+                    })
+                    .lines
+                    .push(line);
+            }
+        }
+    }
+    hunks.extend(current);
+    hunks
+}
+
+fn overlaps(a: &Hunk, b: &Hunk) -> bool {
+    a.start == b.start || (a.start < b.end && b.start < a.end)
+}
+
+/// Line based three-way merge. Returns `None` if both sides changed the same lines differently.
+pub fn merge3(base: &str, local: &str, remote: &str) -> Option<String> {
+    let base_lines: Vec<&str> = base.lines().collect();
+    let local_lines: Vec<&str> = local.lines().collect();
+    let remote_lines: Vec<&str> = remote.lines().collect();
+
+    let local_hunks = hunks(&base_lines, &local_lines);
+    let remote_hunks = hunks(&base_lines, &remote_lines);
+
+    let mut merged: Vec<&str> = Vec::new();
+    let mut base_index = 0;
+    let (mut i, mut j) = (0, 0);
+    loop {
+        let hunk = match (local_hunks.get(i), remote_hunks.get(j)) {
+            (None, None) => break,
+            (Some(local_hunk), Some(remote_hunk)) if overlaps(local_hunk, remote_hunk) => {
+                if local_hunk.start != remote_hunk.start
+                    || local_hunk.end != remote_hunk.end
+                    || local_hunk.lines != remote_hunk.lines
+                {
+                    return None;
+                }
+                // Both sides made the same change.
+                i += 1;
+                j += 1;
+                local_hunk
+            }
+            (Some(local_hunk), Some(remote_hunk)) if local_hunk.start < remote_hunk.start => {
+                i += 1;
+                local_hunk
+            }
+            (_, Some(remote_hunk)) => {
+                j += 1;
+                remote_hunk
+            }
+            (Some(local_hunk), None) => {
+                i += 1;
+                local_hunk
+            }
+        };
+        merged.extend(&base_lines[base_index..hunk.start]);
+        merged.extend(&hunk.lines);
+        base_index = hunk.end;
+    }
+    merged.extend(&base_lines[base_index..]);
+
+    let mut merged = merged.join("\n");
+    if remote.ends_with('\n') || local.ends_with('\n') {
+        merged.push('\n');
+    }
+    Some(merged)
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq)]
+struct BaseRecord {
+    last_modified: i64,
+    hash: String,
+}
+
+fn content_hash(content: &[u8]) -> String {
+    format!("{:x}", sha2::Sha256::digest(content))
+}
+
+/// Last synced copies of text files matching `merge_globs`, used as the base of a three-way
+/// merge. Stored in `program_data_directory/base`.
+pub struct BaseStore {
+    base_directory: path::PathBuf,
+    storage_directory: path::PathBuf,
+    merge_globs: Vec<glob::Pattern>,
+    records: HashMap<path::PathBuf, BaseRecord>,
+}
+
+impl BaseStore {
+    pub fn init(
+        file_handler_config: &client_database::FileHandlerConfig,
+        merge_globs: &[String],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let base_directory = file_handler_config.program_data_directory.join("base");
+        let index_path = base_directory.join("index");
+        let records = if index_path.exists() {
+            bincode::deserialize(&fs::read(&index_path)?)?
+        } else {
+            HashMap::new()
+        };
+        let merge_globs = merge_globs
+            .iter()
+            .map(|merge_glob| glob::Pattern::new(merge_glob))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            base_directory,
+            storage_directory: file_handler_config.storage_directory.clone(),
+            merge_globs,
+            records,
+        })
+    }
+
+    pub fn is_mergeable(&self, relative_path: &path::Path) -> bool {
+        self.merge_globs
+            .iter()
+            .any(|merge_glob| merge_glob.matches_path(relative_path))
+    }
+
+    fn base_path(&self, relative_path: &path::Path) -> path::PathBuf {
+        self.base_directory.join("files").join(relative_path)
+    }
+
+    /// Returns the base copy of the file, provided it is still intact.
+    pub fn read(&self, relative_path: &path::Path) -> Option<String> {
+        let record = self.records.get(relative_path)?;
+        let content = fs::read(self.base_path(relative_path)).ok()?;
+        if content_hash(&content) != record.hash {
+            log::warn!("Base copy of `{}` is corrupt", relative_path.display());
+            return None;
+        }
+        log::debug!(
+            "Using base copy of `{}` synced at {}",
+            relative_path.display(),
+            record.last_modified
+        );
+        String::from_utf8(content).ok()
+    }
+
+    fn record(&mut self, relative_path: &path::Path) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.is_mergeable(relative_path) {
+            return Ok(());
+        }
+        let storage_path = self.storage_directory.join(relative_path);
+        if !storage_path.is_file() {
+            // Not downloaded, e.g. a placeholder.
+            return self.remove(relative_path);
+        }
+        let content = fs::read(&storage_path)?;
+        if std::str::from_utf8(&content).is_err() {
+            return self.remove(relative_path);
+        }
+
+        let base_path = self.base_path(relative_path);
+        if let Some(parent) = base_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&base_path, &content)?;
+        self.records.insert(
+            relative_path.to_path_buf(),
+            BaseRecord {
+                last_modified: client_database::CustomMetadata::last_modified_of_file(
+                    &storage_path,
+                )?,
+                hash: content_hash(&content),
+            },
+        );
+        Ok(())
+    }
+
+    fn remove(&mut self, relative_path: &path::Path) -> Result<(), Box<dyn std::error::Error>> {
+        let base_path = self.base_path(relative_path);
+        if base_path.is_dir() {
+            fs::remove_dir_all(&base_path)?;
+        } else if base_path.exists() {
+            fs::remove_file(&base_path)?;
+        }
+        self.records
+            .retain(|path, _| !path.starts_with(relative_path));
+        Ok(())
+    }
+
+    fn rename(
+        &mut self,
+        from_path: &path::Path,
+        to_path: &path::Path,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let from_base_path = self.base_path(from_path);
+        if from_base_path.exists() {
+            let to_base_path = self.base_path(to_path);
+            if let Some(parent) = to_base_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(&from_base_path, &to_base_path)?;
+        }
+        let moved: Vec<path::PathBuf> = self
+            .records
+            .keys()
+            .filter(|path| path.starts_with(from_path))
+            .cloned()
+            .collect();
+        for path in moved {
+            if let Some(record) = self.records.remove(&path) {
+                let relative = path.strip_prefix(from_path).unwrap_or(&path);
+                self.records.insert(to_path.join(relative), record);
+            }
+        }
+        Ok(())
+    }
+
+    /// Keeps the base copies in step with a change that is now in sync with the server.
+    pub fn update(
+        &mut self,
+        change_event: &data::ChangeEvent,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match change_event {
+            data::ChangeEvent::File(data::FileEvent::Create(file_create)) => {
+                self.record(path::Path::new(file_create.path()))?;
+            }
+            data::ChangeEvent::File(data::FileEvent::Modify(file_modify)) => {
+                self.record(path::Path::new(file_modify.path()))?;
+            }
+            data::ChangeEvent::File(data::FileEvent::Delete(file_delete)) => {
+                self.remove(path::Path::new(file_delete.path()))?;
+            }
+            data::ChangeEvent::File(data::FileEvent::Move(file_move)) => {
+                self.rename(
+                    path::Path::new(file_move.from_path()),
+                    path::Path::new(file_move.to_path()),
+                )?;
+            }
+            data::ChangeEvent::Directory(data::DirectoryEvent::Delete(directory_delete)) => {
+                self.remove(path::Path::new(directory_delete.path()))?;
+            }
+            data::ChangeEvent::Directory(data::DirectoryEvent::Move(directory_move)) => {
+                self.rename(
+                    path::Path::new(directory_move.from_path()),
+                    path::Path::new(directory_move.to_path()),
+                )?;
+            }
+            _ => return Ok(()),
+        }
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        fs::create_dir_all(&self.base_directory)?;
+        fs::write(
+            self.base_directory.join("index"),
+            bincode::serialize(&self.records)?,
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn non_overlapping_hunks_merge() {
+        let base = "a\nb\nc\nd\n";
+        let local = "a local\nb\nc\nd\n";
+        let remote = "a\nb\nc\nd remote\ne\n";
+        assert_eq!(
+            merge3(base, local, remote).as_deref(),
+            Some("a local\nb\nc\nd remote\ne\n")
+        );
+    }
+
+    #[test]
+    fn identical_changes_merge_once() {
+        let base = "a\nb\n";
+        let changed = "a\nb changed\n";
+        assert_eq!(merge3(base, changed, changed).as_deref(), Some(changed));
+    }
+
+    #[test]
+    fn overlapping_hunks_conflict() {
+        let base = "a\nb\nc\n";
+        assert_eq!(merge3(base, "a\nb local\nc\n", "a\nb remote\nc\n"), None);
+        // Insertions at the same place conflict too.
+        assert_eq!(merge3(base, "a\nlocal\nb\nc\n", "a\nremote\nb\nc\n"), None);
+    }
+}
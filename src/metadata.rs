@@ -0,0 +1,57 @@
+use std::fs;
+
+use hcs_lib::client_database;
+
+/// The custom metadata (`.sc`) file of a path, including the fields only the client uses.
+/// `client_database::CustomMetadata::write_to_file` drops the extra fields, which is what should
+/// happen when a file is downloaded again.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq)]
+pub struct Metadata {
+    last_modified: i64,
+    // Set while the file is cloud-only: the size of the server copy that was not downloaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    placeholder_size: Option<u64>,
+}
+
+impl Metadata {
+    pub fn new(last_modified: i64) -> Self {
+        Self {
+            last_modified,
+            placeholder_size: None,
+        }
+    }
+
+    pub fn read(
+        file_paths: &client_database::FilePaths,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let bytes = fs::read(&file_paths.custom_metadata_path())?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    pub fn write(
+        &self,
+        file_paths: &client_database::FilePaths,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        fs::write(
+            &file_paths.custom_metadata_path(),
+            serde_json::to_vec(self)?,
+        )?;
+        Ok(())
+    }
+
+    pub fn last_modified(&self) -> i64 {
+        self.last_modified
+    }
+
+    pub fn set_last_modified(&mut self, last_modified: i64) {
+        self.last_modified = last_modified;
+    }
+
+    pub fn placeholder_size(&self) -> Option<u64> {
+        self.placeholder_size
+    }
+
+    pub fn set_placeholder_size(&mut self, placeholder_size: Option<u64>) {
+        self.placeholder_size = placeholder_size;
+    }
+}
@@ -0,0 +1,305 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use hcs_lib::client_database;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{engine::AsyncResult, errors, rate_limit};
+
+/// `[metrics]` in `Config.toml`.
+#[derive(serde::Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct MetricsConfig {
+    // `host:port` or `unix:/path/to.sock`. Live mode serves no metrics without it.
+    #[serde(default)]
+    pub listen: Option<String>,
+}
+
+// Counters for the whole process, fed by every sync regardless of which engine runs it.
+struct Metrics {
+    bytes_up: AtomicU64,
+    bytes_down: AtomicU64,
+    changes_up: AtomicU64,
+    changes_down: AtomicU64,
+    reconnects: AtomicU64,
+    last_successful_sync: AtomicI64,
+    errors: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+static METRICS: Metrics = Metrics {
+    bytes_up: AtomicU64::new(0),
+    bytes_down: AtomicU64::new(0),
+    changes_up: AtomicU64::new(0),
+    changes_down: AtomicU64::new(0),
+    reconnects: AtomicU64::new(0),
+    last_successful_sync: AtomicI64::new(0),
+    errors: Mutex::new(BTreeMap::new()),
+};
+
+pub(crate) fn record_bytes(direction: rate_limit::Direction, bytes: u64) {
+    match direction {
+        rate_limit::Direction::Upload => METRICS.bytes_up.fetch_add(bytes, Ordering::Relaxed),
+        rate_limit::Direction::Download => METRICS.bytes_down.fetch_add(bytes, Ordering::Relaxed),
+    };
+}
+
+pub(crate) fn record_change(direction: rate_limit::Direction) {
+    match direction {
+        rate_limit::Direction::Upload => METRICS.changes_up.fetch_add(1, Ordering::Relaxed),
+        rate_limit::Direction::Download => METRICS.changes_down.fetch_add(1, Ordering::Relaxed),
+    };
+}
+
+pub(crate) fn record_reconnect() {
+    METRICS.reconnects.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn record_successful_sync() {
+    METRICS
+        .last_successful_sync
+        .store(chrono::Local::now().timestamp(), Ordering::Relaxed);
+}
+
+pub(crate) fn record_error(err: &(dyn std::error::Error + 'static)) {
+    let kind = match err.downcast_ref::<errors::ClientError>() {
+        Some(errors::ClientError::ServerAhead) => "server_ahead",
+        Some(errors::ClientError::Cancelled) => "cancelled",
+        Some(errors::ClientError::AlreadyRunning(_)) => "already_running",
+        Some(errors::ClientError::OutOfSequence { .. }) => "out_of_sequence",
+        None if errors::is_connection_error(err) => "connection",
+        None => "other",
+    };
+    *METRICS.errors.lock().unwrap().entry(kind).or_insert(0) += 1;
+}
+
+fn pending_changes(file_handler_config: &client_database::FileHandlerConfig) -> usize {
+    fs::read_dir(file_handler_config.program_data_directory.join("changes"))
+        .map(|changes| changes.count())
+        .unwrap_or(0)
+}
+
+// The counter that numbers recorded changes, which keeps counting while `changes/` is optimized.
+fn change_count(file_handler_config: &client_database::FileHandlerConfig) -> u64 {
+    fs::read_to_string(
+        file_handler_config
+            .program_data_directory
+            .join("change_count"),
+    )
+    .ok()
+    .and_then(|change_count| change_count.trim().parse().ok())
+    .unwrap_or(0)
+}
+
+/// The metrics in the Prometheus text format.
+pub fn render(file_handler_config: &client_database::FileHandlerConfig) -> String {
+    let server_version =
+        client_database::ServerVersion::init(&file_handler_config.program_data_directory)
+            .server_version();
+
+    let mut body = String::new();
+    let _ = writeln!(
+        body,
+        "# HELP hcs_bytes_total File content bytes transferred."
+    );
+    let _ = writeln!(body, "# TYPE hcs_bytes_total counter");
+    let _ = writeln!(
+        body,
+        "hcs_bytes_total{{direction=\"up\"}} {}",
+        METRICS.bytes_up.load(Ordering::Relaxed)
+    );
+    let _ = writeln!(
+        body,
+        "hcs_bytes_total{{direction=\"down\"}} {}",
+        METRICS.bytes_down.load(Ordering::Relaxed)
+    );
+    let _ = writeln!(body, "# HELP hcs_changes_total Changes synced.");
+    let _ = writeln!(body, "# TYPE hcs_changes_total counter");
+    let _ = writeln!(
+        body,
+        "hcs_changes_total{{direction=\"up\"}} {}",
+        METRICS.changes_up.load(Ordering::Relaxed)
+    );
+    let _ = writeln!(
+        body,
+        "hcs_changes_total{{direction=\"down\"}} {}",
+        METRICS.changes_down.load(Ordering::Relaxed)
+    );
+    let _ = writeln!(
+        body,
+        "# HELP hcs_pending_changes Local changes recorded but not synced yet."
+    );
+    let _ = writeln!(body, "# TYPE hcs_pending_changes gauge");
+    let _ = writeln!(
+        body,
+        "hcs_pending_changes {}",
+        pending_changes(file_handler_config)
+    );
+    let _ = writeln!(
+        body,
+        "# HELP hcs_change_count Changes recorded locally, as numbered in `change_count`."
+    );
+    let _ = writeln!(body, "# TYPE hcs_change_count gauge");
+    let _ = writeln!(
+        body,
+        "hcs_change_count {}",
+        change_count(file_handler_config)
+    );
+    let _ = writeln!(
+        body,
+        "# HELP hcs_last_successful_sync_timestamp_seconds When the last sync finished without errors."
+    );
+    let _ = writeln!(
+        body,
+        "# TYPE hcs_last_successful_sync_timestamp_seconds gauge"
+    );
+    let _ = writeln!(
+        body,
+        "hcs_last_successful_sync_timestamp_seconds {}",
+        METRICS.last_successful_sync.load(Ordering::Relaxed)
+    );
+    let _ = writeln!(
+        body,
+        "# HELP hcs_server_version The server version this client is synced to."
+    );
+    let _ = writeln!(body, "# TYPE hcs_server_version gauge");
+    let _ = writeln!(body, "hcs_server_version {}", server_version);
+    let _ = writeln!(body, "# HELP hcs_errors_total Failed syncs by error kind.");
+    let _ = writeln!(body, "# TYPE hcs_errors_total counter");
+    for (kind, count) in METRICS.errors.lock().unwrap().iter() {
+        let _ = writeln!(body, "hcs_errors_total{{kind=\"{}\"}} {}", kind, count);
+    }
+    let _ = writeln!(
+        body,
+        "# HELP hcs_reconnects_total Reconnects after a dropped or timed out connection."
+    );
+    let _ = writeln!(body, "# TYPE hcs_reconnects_total counter");
+    let _ = writeln!(
+        body,
+        "hcs_reconnects_total {}",
+        METRICS.reconnects.load(Ordering::Relaxed)
+    );
+    body
+}
+
+// Answers any HTTP request with the metrics.
+async fn respond(
+    mut stream: impl AsyncRead + AsyncWrite + Unpin,
+    file_handler_config: Arc<client_database::FileHandlerConfig>,
+) {
+    let mut request = [0; 1024];
+    if stream.read(&mut request).await.is_err() {
+        return;
+    }
+    let body = render(&file_handler_config);
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+#[cfg(unix)]
+async fn serve_unix(
+    socket_path: &str,
+    file_handler_config: Arc<client_database::FileHandlerConfig>,
+) -> AsyncResult<()> {
+    // A socket nobody answers on was left behind by a live mode that did not stop cleanly.
+    let socket_path = std::path::Path::new(socket_path);
+    if socket_path.exists() {
+        if std::os::unix::net::UnixStream::connect(socket_path).is_ok() {
+            return Err(format!(
+                "Something is already listening on {}",
+                socket_path.display()
+            )
+            .into());
+        }
+        fs::remove_file(socket_path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(socket_path)?;
+    log::info!("Serving metrics on unix:{}", socket_path.display());
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(respond(stream, Arc::clone(&file_handler_config)));
+    }
+}
+
+#[cfg(not(unix))]
+async fn serve_unix(
+    _socket_path: &str,
+    _file_handler_config: Arc<client_database::FileHandlerConfig>,
+) -> AsyncResult<()> {
+    Err("Unix sockets are not supported on this platform".into())
+}
+
+/// Serves the metrics on `listen` until the task is aborted.
+pub async fn serve(
+    listen: String,
+    file_handler_config: client_database::FileHandlerConfig,
+) -> AsyncResult<()> {
+    let file_handler_config = Arc::new(file_handler_config);
+    if let Some(socket_path) = listen.strip_prefix("unix:") {
+        return serve_unix(socket_path, file_handler_config).await;
+    }
+
+    let listener = tokio::net::TcpListener::bind(&listen).await?;
+    log::info!("Serving metrics on {}", listen);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(respond(stream, Arc::clone(&file_handler_config)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pending_changes_come_from_the_change_files_and_counter() {
+        let directory = tempfile::tempdir().unwrap();
+        let file_handler_config = client_database::FileHandlerConfig {
+            storage_directory: directory.path().join("storage"),
+            symlink_directory: directory.path().join("symlink"),
+            temporary_directory: directory.path().join("temporary"),
+            program_data_directory: directory.path().join("program_data"),
+        };
+        let program_data_directory = &file_handler_config.program_data_directory;
+        assert_eq!(pending_changes(&file_handler_config), 0);
+        assert_eq!(change_count(&file_handler_config), 0);
+
+        fs::create_dir_all(program_data_directory.join("changes")).unwrap();
+        fs::write(program_data_directory.join("changes/4.tmp"), "").unwrap();
+        fs::write(program_data_directory.join("changes/5.tmp"), "").unwrap();
+        fs::write(program_data_directory.join("change_count"), "6\n").unwrap();
+        assert_eq!(pending_changes(&file_handler_config), 2);
+        assert_eq!(change_count(&file_handler_config), 6);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn serving_on_a_socket_in_use_fails() {
+        let directory = tempfile::tempdir().unwrap();
+        let socket_path = directory.path().join("metrics.sock");
+        let _listener = std::os::unix::net::UnixListener::bind(&socket_path).unwrap();
+        let file_handler_config = Arc::new(client_database::FileHandlerConfig {
+            storage_directory: directory.path().join("storage"),
+            symlink_directory: directory.path().join("symlink"),
+            temporary_directory: directory.path().join("temporary"),
+            program_data_directory: directory.path().join("program_data"),
+        });
+
+        assert!(
+            serve_unix(socket_path.to_str().unwrap(), file_handler_config)
+                .await
+                .is_err()
+        );
+        assert!(socket_path.exists());
+    }
+}
@@ -0,0 +1,191 @@
+use std::{fs, path};
+
+use hcs_lib::{client_database, data};
+
+use crate::{config, placeholders};
+
+/// Paths that are always kept downloaded, including everything below a pinned directory. Stored
+/// in `program_data_directory/pins` rather than the `.sc` files: their `CustomMetadata` format
+/// belongs to `hcs_lib`, and every download rewrites them with only the last modified time.
+pub struct Pins {
+    pins_path: path::PathBuf,
+    paths: Vec<path::PathBuf>,
+}
+
+impl Pins {
+    pub fn init(
+        file_handler_config: &client_database::FileHandlerConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let pins_path = file_handler_config.program_data_directory.join("pins");
+        let paths = if pins_path.exists() {
+            bincode::deserialize(&fs::read(&pins_path)?)?
+        } else {
+            Vec::new()
+        };
+        Ok(Self { pins_path, paths })
+    }
+
+    pub fn paths(&self) -> &[path::PathBuf] {
+        &self.paths
+    }
+
+    /// Whether the path itself is pinned, ignoring its parent directories.
+    pub fn is_pinned_itself(&self, relative_path: &path::Path) -> bool {
+        self.paths.iter().any(|path| path == relative_path)
+    }
+
+    pub fn is_pinned(&self, relative_path: &path::Path) -> bool {
+        self.paths
+            .iter()
+            .any(|path| relative_path.starts_with(path))
+    }
+
+    fn set_pinned(
+        &mut self,
+        relative_path: &path::Path,
+        pinned: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.paths.retain(|path| path != relative_path);
+        if pinned {
+            self.paths.push(relative_path.to_path_buf());
+            self.paths.sort();
+        }
+        self.save()
+    }
+
+    fn remove_within(&mut self, relative_path: &path::Path) {
+        self.paths.retain(|path| !path.starts_with(relative_path));
+    }
+
+    fn rename(&mut self, from_path: &path::Path, to_path: &path::Path) {
+        for path in self.paths.iter_mut() {
+            if let Ok(relative) = path.strip_prefix(from_path) {
+                *path = to_path.join(relative);
+            }
+        }
+        self.paths.sort();
+    }
+
+    /// Keeps the pins on paths that were moved or deleted, locally or on the server.
+    pub fn update(
+        &mut self,
+        change_event: &data::ChangeEvent,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match change_event {
+            data::ChangeEvent::File(data::FileEvent::Delete(file_delete)) => {
+                self.remove_within(path::Path::new(file_delete.path()));
+            }
+            data::ChangeEvent::File(data::FileEvent::Move(file_move)) => {
+                self.rename(
+                    path::Path::new(file_move.from_path()),
+                    path::Path::new(file_move.to_path()),
+                );
+            }
+            data::ChangeEvent::Directory(data::DirectoryEvent::Delete(directory_delete)) => {
+                self.remove_within(path::Path::new(directory_delete.path()));
+            }
+            data::ChangeEvent::Directory(data::DirectoryEvent::Move(directory_move)) => {
+                self.rename(
+                    path::Path::new(directory_move.from_path()),
+                    path::Path::new(directory_move.to_path()),
+                );
+            }
+            _ => return Ok(()),
+        }
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        fs::write(&self.pins_path, bincode::serialize(&self.paths)?)?;
+        Ok(())
+    }
+}
+
+pub fn is_pinned(
+    file_handler_config: &client_database::FileHandlerConfig,
+    relative_path: &path::Path,
+) -> bool {
+    Pins::init(file_handler_config)
+        .map(|pins| pins.is_pinned(relative_path))
+        .unwrap_or(false)
+}
+
+pub fn pinned_paths(
+    file_handler_config: &client_database::FileHandlerConfig,
+) -> Result<Vec<path::PathBuf>, Box<dyn std::error::Error>> {
+    Ok(Pins::init(file_handler_config)?.paths().to_vec())
+}
+
+/// Keeps a file or directory downloaded, hydrating any placeholders below it first.
+pub fn pin(
+    config: &config::ClientConfig,
+    relative_path: &path::Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file_handler_config = config.file_handler_config();
+    if !file_handler_config
+        .storage_directory
+        .join(relative_path)
+        .exists()
+        && !placeholders::is_placeholder(file_handler_config, relative_path)
+    {
+        return Err(format!("`{}` is not synced", relative_path.display()).into());
+    }
+    placeholders::hydrate(config, relative_path)?;
+    Pins::init(file_handler_config)?.set_pinned(relative_path, true)?;
+    log::info!("Pinned `{}`", relative_path.display());
+    Ok(())
+}
+
+pub fn unpin(
+    config: &config::ClientConfig,
+    relative_path: &path::Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut pins = Pins::init(config.file_handler_config())?;
+    if !pins.is_pinned_itself(relative_path) {
+        if pins.is_pinned(relative_path) {
+            return Err(format!(
+                "`{}` is pinned through a parent directory",
+                relative_path.display()
+            )
+            .into());
+        }
+        return Ok(());
+    }
+    pins.set_pinned(relative_path, false)?;
+    log::info!("Unpinned `{}`", relative_path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pins(paths: &[&str]) -> Pins {
+        Pins {
+            pins_path: path::PathBuf::new(),
+            paths: paths.iter().map(path::PathBuf::from).collect(),
+        }
+    }
+
+    #[test]
+    fn pinned_directories_pin_everything_below() {
+        let pins = pins(&["photos"]);
+        assert!(pins.is_pinned(path::Path::new("photos/2023/a.jpg")));
+        assert!(!pins.is_pinned_itself(path::Path::new("photos/2023/a.jpg")));
+        assert!(!pins.is_pinned(path::Path::new("photos-old/a.jpg")));
+    }
+
+    #[test]
+    fn pins_follow_moves_and_deletes() {
+        let mut pins = pins(&["docs/a.txt", "photos/2023", "photos/2024"]);
+        pins.rename(path::Path::new("photos"), path::Path::new("archive/photos"));
+        pins.remove_within(path::Path::new("docs"));
+        assert_eq!(
+            pins.paths(),
+            [
+                path::PathBuf::from("archive/photos/2023"),
+                path::PathBuf::from("archive/photos/2024")
+            ]
+        );
+    }
+}
@@ -0,0 +1,180 @@
+use std::{fs, path};
+
+use hcs_lib::{client_database, data};
+
+use crate::{changes, config, metadata, quota, remote};
+
+fn file_paths(
+    file_handler_config: &client_database::FileHandlerConfig,
+    relative_path: &path::Path,
+) -> Result<client_database::FilePaths, Box<dyn std::error::Error>> {
+    client_database::FilePaths::from_relative_path(
+        relative_path.to_path_buf(),
+        client_database::Type::File,
+        client_database::FileLocation::StorageDir,
+        None,
+        file_handler_config,
+    )
+}
+
+pub fn is_placeholder(
+    file_handler_config: &client_database::FileHandlerConfig,
+    relative_path: &path::Path,
+) -> bool {
+    file_paths(file_handler_config, relative_path)
+        .and_then(|file_paths| metadata::Metadata::read(&file_paths))
+        .map(|metadata| metadata.placeholder_size().is_some())
+        .unwrap_or(false)
+}
+
+/// Relative paths of the files within `relative_path` (or the file itself) that are downloaded
+/// (`placeholders == false`) or cloud-only (`placeholders == true`).
+pub fn files_within(
+    file_handler_config: &client_database::FileHandlerConfig,
+    relative_path: &path::Path,
+    placeholders: bool,
+) -> Result<Vec<path::PathBuf>, Box<dyn std::error::Error>> {
+    let storage_path = file_handler_config.storage_directory.join(relative_path);
+    if !storage_path.is_dir() {
+        return Ok(
+            if is_placeholder(file_handler_config, relative_path) == placeholders {
+                vec![relative_path.to_path_buf()]
+            } else {
+                vec![]
+            },
+        );
+    }
+
+    let mut files = Vec::new();
+    for entry in fs::read_dir(&storage_path)? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        // Placeholders only leave their `.<name>.sc` metadata file in the storage directory.
+        let name = match file_name
+            .strip_prefix('.')
+            .and_then(|name| name.strip_suffix(".sc"))
+        {
+            Some(name) => name,
+            None => continue,
+        };
+        files.extend(files_within(
+            file_handler_config,
+            &relative_path.join(name),
+            placeholders,
+        )?);
+    }
+    Ok(files)
+}
+
+/// Frees the local copy of a synced file, leaving a placeholder that records its size.
+pub fn dehydrate_file(
+    file_handler_config: &client_database::FileHandlerConfig,
+    relative_path: &path::Path,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    if changes::PendingChanges::read(file_handler_config).contains(relative_path) {
+        return Err(format!(
+            "`{}` has changes that are not synced yet",
+            relative_path.display()
+        )
+        .into());
+    }
+
+    let file_paths = file_paths(file_handler_config, relative_path)?;
+    let mut metadata = metadata::Metadata::read(&file_paths)?;
+    if metadata.placeholder_size().is_some() {
+        return Ok(0);
+    }
+
+    let size = fs::metadata(&file_paths.storage_dir_path())?.len();
+    metadata.set_placeholder_size(Some(size));
+    metadata.write(&file_paths)?;
+    // The symlink is kept, pointing at the missing file until it is hydrated again.
+    fs::remove_file(&file_paths.storage_dir_path())?;
+
+    log::info!("Dehydrated `{}` ({} bytes)", relative_path.display(), size);
+    Ok(size)
+}
+
+pub fn hydrate_file(
+    config: &config::ClientConfig,
+    relative_path: &path::Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file_paths = file_paths(config.file_handler_config(), relative_path)?;
+
+    remote::fetch_file(
+        config,
+        &relative_path.to_string_lossy(),
+        &file_paths.storage_dir_path(),
+    )?;
+
+    let last_modified =
+        client_database::CustomMetadata::last_modified_of_file(&file_paths.storage_dir_path())?;
+    let custom_metadata = client_database::CustomMetadata::new(last_modified);
+    custom_metadata.write_to_file(&file_paths)?;
+
+    if fs::read_link(&file_paths.symlink_dir_path()).is_err() {
+        symlink::symlink_file(
+            &file_paths.storage_dir_path(),
+            &file_paths.symlink_dir_path(),
+        )?;
+    }
+
+    log::info!("Hydrated `{}`", relative_path.display());
+    Ok(())
+}
+
+/// Returns the storage path of a file, downloading its contents first if it is a placeholder.
+pub fn ensure_hydrated(
+    config: &config::ClientConfig,
+    relative_path: &path::Path,
+) -> Result<path::PathBuf, Box<dyn std::error::Error>> {
+    if is_placeholder(config.file_handler_config(), relative_path) {
+        hydrate_file(config, relative_path)?;
+    }
+    Ok(config
+        .file_handler_config()
+        .storage_directory
+        .join(relative_path))
+}
+
+pub fn hydrate(
+    config: &config::ClientConfig,
+    relative_path: &path::Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for placeholder in files_within(config.file_handler_config(), relative_path, true)? {
+        hydrate_file(config, &placeholder)?;
+    }
+    quota::enforce(config)
+}
+
+pub fn dehydrate(
+    config: &config::ClientConfig,
+    relative_path: &path::Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut freed = 0;
+    for file in files_within(config.file_handler_config(), relative_path, false)? {
+        match dehydrate_file(config.file_handler_config(), &file) {
+            Ok(size) => freed += size,
+            Err(err) => log::warn!("Skipped `{}`: {}", file.display(), err),
+        }
+    }
+    println!("Freed {} bytes", freed);
+    Ok(())
+}
+
+/// Deletes change files recording the missing contents of placeholders as deletions.
+pub fn discard_placeholder_deletions(
+    file_handler_config: &client_database::FileHandlerConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for (change_id, change_event) in client_database::read_changes(file_handler_config) {
+        if let data::ChangeEvent::File(data::FileEvent::Delete(file_delete)) = &change_event {
+            if is_placeholder(file_handler_config, path::Path::new(file_delete.path())) {
+                let change_file = changes::change_file_path(file_handler_config, change_id);
+                if change_file.exists() {
+                    fs::remove_file(change_file)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,367 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, IsTerminal, Write},
+    sync::Mutex,
+    time,
+};
+
+use crate::{metrics, rate_limit};
+
+const BAR_INTERVAL: time::Duration = time::Duration::from_millis(100);
+const JSON_INTERVAL: time::Duration = time::Duration::from_secs(1);
+const BAR_WIDTH: usize = 30;
+
+#[derive(Debug)]
+struct FileReport<'a> {
+    path: &'a str,
+    bytes_done: u64,
+    size: u64,
+}
+
+#[derive(Debug)]
+struct Report<'a> {
+    direction: &'static str,
+    changes_done: usize,
+    total_changes: Option<usize>,
+    bytes_done: u64,
+    total_bytes: Option<u64>,
+    bytes_per_sec: u64,
+    eta_secs: Option<u64>,
+    files: Vec<FileReport<'a>>,
+}
+
+struct ActiveFile {
+    path: String,
+    size: u64,
+    bytes_done: u64,
+}
+
+#[derive(Default)]
+struct State {
+    changes_done: usize,
+    bytes_done: u64,
+    active_files: BTreeMap<u64, ActiveFile>,
+    next_file_id: u64,
+    last_render: Option<time::Instant>,
+}
+
+/// Progress of one sync transmission. It is drawn as a progress bar when stderr is a terminal.
+/// Otherwise it is logged at info level every second with its numbers as key-values, so it goes
+/// through the same logger and format as every other line on stderr.
+pub struct Progress {
+    direction: rate_limit::Direction,
+    total_changes: Option<usize>,
+    total_bytes: Option<u64>,
+    terminal: bool,
+    started: time::Instant,
+    state: Mutex<State>,
+}
+
+/// A file being transferred. It stops being reported once dropped.
+pub struct FileProgress<'a> {
+    progress: &'a Progress,
+    id: u64,
+}
+
+impl Progress {
+    pub fn new(
+        direction: rate_limit::Direction,
+        total_changes: Option<usize>,
+        total_bytes: Option<u64>,
+    ) -> Self {
+        Self {
+            direction,
+            total_changes,
+            total_bytes,
+            terminal: io::stderr().is_terminal(),
+            started: time::Instant::now(),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    pub fn file(&self, path: &str, size: u64) -> FileProgress<'_> {
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_file_id;
+        state.next_file_id += 1;
+        state.active_files.insert(
+            id,
+            ActiveFile {
+                path: path.to_string(),
+                size,
+                bytes_done: 0,
+            },
+        );
+        FileProgress { progress: self, id }
+    }
+
+    pub fn change_done(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.changes_done += 1;
+        metrics::record_change(self.direction);
+        self.render(&mut state, false);
+    }
+
+    // `elapsed` is the time since the transmission started.
+    fn report<'a>(&self, state: &'a State, elapsed: time::Duration) -> Report<'a> {
+        let elapsed = elapsed.as_secs_f64();
+        let bytes_per_sec = if elapsed > 0.0 {
+            (state.bytes_done as f64 / elapsed) as u64
+        } else {
+            0
+        };
+        // Without a total, only a lone file has a known remainder.
+        let bytes_remaining = match self.total_bytes {
+            Some(total_bytes) => Some(total_bytes.saturating_sub(state.bytes_done)),
+            None if state.active_files.len() == 1 => state
+                .active_files
+                .values()
+                .next()
+                .map(|file| file.size.saturating_sub(file.bytes_done)),
+            None => None,
+        };
+        Report {
+            direction: match self.direction {
+                rate_limit::Direction::Upload => "upload",
+                rate_limit::Direction::Download => "download",
+            },
+            changes_done: state.changes_done,
+            total_changes: self.total_changes,
+            bytes_done: state.bytes_done,
+            total_bytes: self.total_bytes,
+            bytes_per_sec,
+            eta_secs: bytes_remaining
+                .filter(|_| bytes_per_sec > 0)
+                .map(|bytes_remaining| bytes_remaining / bytes_per_sec),
+            files: state
+                .active_files
+                .values()
+                .map(|file| FileReport {
+                    path: &file.path,
+                    bytes_done: file.bytes_done,
+                    size: file.size,
+                })
+                .collect(),
+        }
+    }
+
+    fn render(&self, state: &mut State, force: bool) {
+        let now = time::Instant::now();
+        let interval = if self.terminal {
+            BAR_INTERVAL
+        } else {
+            JSON_INTERVAL
+        };
+        if !force
+            && state
+                .last_render
+                .is_some_and(|last_render| now.duration_since(last_render) < interval)
+        {
+            return;
+        }
+        state.last_render = Some(now);
+
+        let report = self.report(state, self.started.elapsed());
+        if self.terminal {
+            let mut stderr = io::stderr().lock();
+            let _ = write!(stderr, "\r\x1b[2K{}", bar_line(self.direction, &report));
+            let _ = stderr.flush();
+            return;
+        }
+        let path = match report.files.as_slice() {
+            [file] => Some(file.path),
+            _ => None,
+        };
+        log::info!(
+            direction = report.direction,
+            changes_done = report.changes_done,
+            total_changes = report.total_changes,
+            bytes_done = report.bytes_done,
+            total_bytes = report.total_bytes,
+            bytes_per_sec = report.bytes_per_sec,
+            eta_secs = report.eta_secs,
+            files = report.files.len(),
+            path;
+            "{}", bar_line(self.direction, &report)
+        );
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        if state.last_render.is_none() && state.changes_done == 0 {
+            return;
+        }
+        state.active_files.clear();
+        self.render(&mut state, true);
+        if self.terminal {
+            eprintln!();
+        }
+    }
+}
+
+impl FileProgress<'_> {
+    pub fn advance(&self, bytes: usize) {
+        metrics::record_bytes(self.progress.direction, bytes as u64);
+        let mut state = self.progress.state.lock().unwrap();
+        state.bytes_done += bytes as u64;
+        if let Some(file) = state.active_files.get_mut(&self.id) {
+            file.bytes_done += bytes as u64;
+        }
+        self.progress.render(&mut state, false);
+    }
+}
+
+impl Drop for FileProgress<'_> {
+    fn drop(&mut self) {
+        let mut state = self.progress.state.lock().unwrap();
+        state.active_files.remove(&self.id);
+    }
+}
+
+fn bar_line(direction: rate_limit::Direction, report: &Report) -> String {
+    let mut line = match direction {
+        rate_limit::Direction::Upload => "Uploading".to_string(),
+        rate_limit::Direction::Download => "Downloading".to_string(),
+    };
+    match report.total_changes {
+        Some(total_changes) => line += &format!(" {}/{}", report.changes_done, total_changes),
+        None => line += &format!(" {}", report.changes_done),
+    }
+    line += " changes ";
+
+    match report.total_bytes {
+        Some(total_bytes) if total_bytes > 0 => {
+            let filled = (report.bytes_done.min(total_bytes) as f64 / total_bytes as f64
+                * BAR_WIDTH as f64) as usize;
+            line += &format!(
+                "[{}{}] {}/{}",
+                "=".repeat(filled),
+                " ".repeat(BAR_WIDTH - filled),
+                format_bytes(report.bytes_done),
+                format_bytes(total_bytes)
+            );
+        }
+        _ => line += &format_bytes(report.bytes_done),
+    }
+    line += &format!("  {}/s", format_bytes(report.bytes_per_sec));
+    if let Some(eta_secs) = report.eta_secs {
+        line += &format!("  ETA {}", format_duration(eta_secs));
+    }
+
+    match report.files.as_slice() {
+        [file] if file.size > 0 => {
+            line += &format!(
+                "  {} {}%",
+                file.path,
+                file.bytes_done.min(file.size) * 100 / file.size
+            );
+        }
+        [file] => line += &format!("  {}", file.path),
+        [] => {}
+        files => line += &format!("  {} files", files.len()),
+    }
+    line
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+fn format_duration(secs: u64) -> String {
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(bytes_done: u64, total_bytes: Option<u64>) -> Report<'static> {
+        Report {
+            direction: "upload",
+            changes_done: 1,
+            total_changes: Some(4),
+            bytes_done,
+            total_bytes,
+            bytes_per_sec: 1024,
+            eta_secs: Some(90),
+            files: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn formats_sizes_and_durations() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(5 * 1024 * 1024 * 1024), "5.0 GiB");
+        assert_eq!(format_duration(59), "0:59");
+        assert_eq!(format_duration(3 * 60 + 7), "3:07");
+        assert_eq!(format_duration(2 * 3600 + 5 * 60 + 9), "2:05:09");
+    }
+
+    #[test]
+    fn bar_fills_with_the_bytes_done() {
+        let line = bar_line(rate_limit::Direction::Upload, &report(512, Some(1024)));
+        assert_eq!(
+            line,
+            format!(
+                "Uploading 1/4 changes [{}{}] 512 B/1.0 KiB  1.0 KiB/s  ETA 1:30",
+                "=".repeat(BAR_WIDTH / 2),
+                " ".repeat(BAR_WIDTH / 2)
+            )
+        );
+        // Without a total there is nothing to fill.
+        assert_eq!(
+            bar_line(rate_limit::Direction::Upload, &report(512, None)),
+            "Uploading 1/4 changes 512 B  1.0 KiB/s  ETA 1:30"
+        );
+    }
+
+    #[test]
+    fn lone_file_gives_an_eta_without_a_total() {
+        let progress = Progress::new(rate_limit::Direction::Download, None, None);
+        let mut state = State {
+            bytes_done: 100,
+            ..Default::default()
+        };
+        state.active_files.insert(
+            0,
+            ActiveFile {
+                path: "a.txt".to_string(),
+                size: 1000,
+                bytes_done: 100,
+            },
+        );
+        let elapsed = time::Duration::from_secs(10);
+        let report = progress.report(&state, elapsed);
+        assert_eq!(report.bytes_per_sec, 10);
+        // 900 bytes left at 10 bytes per second.
+        assert_eq!(report.eta_secs, Some(90));
+        assert_eq!(progress.report(&state, time::Duration::ZERO).eta_secs, None);
+
+        state.active_files.insert(
+            1,
+            ActiveFile {
+                path: "b.txt".to_string(),
+                size: 1000,
+                bytes_done: 0,
+            },
+        );
+        assert_eq!(progress.report(&state, elapsed).eta_secs, None);
+    }
+}
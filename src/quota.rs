@@ -0,0 +1,127 @@
+use std::{fs, path, time};
+
+use hcs_lib::client_database;
+
+use crate::{changes, config, metadata, pins, placeholders};
+
+struct StoredFile {
+    relative_path: path::PathBuf,
+    size: u64,
+    accessed: time::SystemTime,
+}
+
+fn read_stored_files(
+    storage_directory: &path::Path,
+    relative_directory: &path::Path,
+    stored_files: &mut Vec<StoredFile>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for entry in fs::read_dir(storage_directory.join(relative_directory))? {
+        let entry = entry?;
+        let relative_path = relative_directory.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            read_stored_files(storage_directory, &relative_path, stored_files)?;
+        } else if file_type.is_file() {
+            let metadata = entry.metadata()?;
+            stored_files.push(StoredFile {
+                relative_path,
+                size: metadata.len(),
+                accessed: metadata.accessed().unwrap_or(time::UNIX_EPOCH),
+            });
+        }
+    }
+    Ok(())
+}
+
+// Only files that are identical to the server copy may be evicted: synced before, not modified
+// since and without pending changes.
+fn is_evictable(
+    file_handler_config: &client_database::FileHandlerConfig,
+    pending_changes: &changes::PendingChanges,
+    pins: &pins::Pins,
+    relative_path: &path::Path,
+) -> bool {
+    let is_custom_metadata = relative_path
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy().ends_with(".sc"))
+        .unwrap_or(true);
+    if is_custom_metadata
+        || relative_path.ends_with(".hcsignore")
+        || pending_changes.contains(relative_path)
+        || pins.is_pinned(relative_path)
+    {
+        return false;
+    }
+
+    let file_paths = match client_database::FilePaths::from_relative_path(
+        relative_path.to_path_buf(),
+        client_database::Type::File,
+        client_database::FileLocation::StorageDir,
+        None,
+        file_handler_config,
+    ) {
+        Ok(file_paths) => file_paths,
+        Err(_) => return false,
+    };
+    let synced_last_modified = match metadata::Metadata::read(&file_paths) {
+        Ok(metadata) => metadata.last_modified(),
+        Err(_) => return false,
+    };
+    match client_database::CustomMetadata::last_modified_of_file(&file_paths.storage_dir_path()) {
+        Ok(last_modified) => last_modified <= synced_last_modified,
+        Err(_) => false,
+    }
+}
+
+/// Evicts the least recently accessed synced files to placeholders until the storage directory
+/// fits in `max_storage_bytes`.
+pub fn enforce(config: &config::ClientConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let max_storage_bytes = match config.max_storage_bytes() {
+        Some(max_storage_bytes) => max_storage_bytes,
+        None => return Ok(()),
+    };
+    let file_handler_config = config.file_handler_config();
+
+    let mut files = Vec::new();
+    read_stored_files(
+        &file_handler_config.storage_directory,
+        path::Path::new(""),
+        &mut files,
+    )?;
+    let mut storage_bytes: u64 = files.iter().map(|file| file.size).sum();
+    if storage_bytes <= max_storage_bytes {
+        return Ok(());
+    }
+
+    log::info!(
+        "Storage directory uses {} of {} bytes, evicting files",
+        storage_bytes,
+        max_storage_bytes
+    );
+    let pending_changes = changes::PendingChanges::read(file_handler_config);
+    let pins = pins::Pins::init(file_handler_config)?;
+    files.sort_by_key(|file| file.accessed);
+    for file in files {
+        if storage_bytes <= max_storage_bytes {
+            break;
+        }
+        if !is_evictable(
+            file_handler_config,
+            &pending_changes,
+            &pins,
+            &file.relative_path,
+        ) {
+            continue;
+        }
+        storage_bytes -= placeholders::dehydrate_file(file_handler_config, &file.relative_path)?;
+    }
+
+    if storage_bytes > max_storage_bytes {
+        log::warn!(
+            "Storage directory still uses {} of {} bytes, nothing else can be evicted",
+            storage_bytes,
+            max_storage_bytes
+        );
+    }
+    Ok(())
+}
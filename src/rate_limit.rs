@@ -0,0 +1,205 @@
+use std::{sync::Mutex, thread, time};
+
+use crate::config;
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Upload,
+    Download,
+}
+
+/// `[rate_limit]` in `Config.toml`. Limits are in bytes per second, and a missing limit means
+/// unlimited.
+#[derive(serde::Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct RateLimitConfig {
+    #[serde(default)]
+    upload_bytes_per_sec: Option<u64>,
+    #[serde(default)]
+    download_bytes_per_sec: Option<u64>,
+    #[serde(default)]
+    schedule: Vec<ScheduleWindow>,
+    // Set by `--limit-rate`, applies to both directions and ignores the schedule.
+    #[serde(skip)]
+    limit_override: Option<u64>,
+}
+
+/// Replaces the limits between `from` and `to` local time, e.g. `22:00` to `06:00`.
+#[derive(serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct ScheduleWindow {
+    #[serde(deserialize_with = "parse_time_of_day")]
+    from: chrono::NaiveTime,
+    #[serde(deserialize_with = "parse_time_of_day")]
+    to: chrono::NaiveTime,
+    #[serde(default)]
+    upload_bytes_per_sec: Option<u64>,
+    #[serde(default)]
+    download_bytes_per_sec: Option<u64>,
+}
+
+fn parse_time_of_day<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<chrono::NaiveTime, D::Error> {
+    let time_of_day: String = serde::Deserialize::deserialize(deserializer)?;
+    chrono::NaiveTime::parse_from_str(&time_of_day, "%H:%M").map_err(serde::de::Error::custom)
+}
+
+impl ScheduleWindow {
+    fn contains(&self, time_of_day: chrono::NaiveTime) -> bool {
+        if self.from <= self.to {
+            self.from <= time_of_day && time_of_day < self.to
+        } else {
+            // Wraps past midnight
+            self.from <= time_of_day || time_of_day < self.to
+        }
+    }
+
+    fn limit(&self, direction: Direction) -> Option<u64> {
+        match direction {
+            Direction::Upload => self.upload_bytes_per_sec,
+            Direction::Download => self.download_bytes_per_sec,
+        }
+    }
+}
+
+impl RateLimitConfig {
+    pub fn override_limit(&mut self, bytes_per_sec: u64) {
+        self.limit_override = Some(bytes_per_sec);
+    }
+
+    /// The limit for `direction` right now, if any.
+    pub fn limit(&self, direction: Direction) -> Option<u64> {
+        if self.limit_override.is_some() {
+            return self.limit_override.filter(|limit| *limit > 0);
+        }
+        let now = chrono::Local::now().time();
+        match self.schedule.iter().find(|window| window.contains(now)) {
+            Some(window) => window.limit(direction),
+            None => match direction {
+                Direction::Upload => self.upload_bytes_per_sec,
+                Direction::Download => self.download_bytes_per_sec,
+            },
+        }
+        .filter(|limit| *limit > 0)
+    }
+}
+
+/// Parses a rate such as `500k`, `2M` or `1048576` into bytes per second.
+pub fn parse_rate(rate: &str) -> Result<u64, Box<dyn std::error::Error>> {
+    let rate = rate.trim();
+    let (number, multiplier) = match rate.chars().last() {
+        Some('k') | Some('K') => (&rate[..rate.len() - 1], 1024),
+        Some('m') | Some('M') => (&rate[..rate.len() - 1], 1024 * 1024),
+        Some('g') | Some('G') => (&rate[..rate.len() - 1], 1024 * 1024 * 1024),
+        _ => (rate, 1),
+    };
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("Invalid rate `{}`, expected e.g. 500k or 2M", rate))?;
+    Ok((number * multiplier as f64) as u64)
+}
+
+struct Bucket {
+    tokens: f64,
+    last_refill: time::Instant,
+}
+
+/// A token bucket for the transfers in one direction. It holds up to one second's worth of
+/// bytes.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    direction: Direction,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &config::ClientConfig, direction: Direction) -> Self {
+        Self {
+            config: config.rate_limit_config().clone(),
+            direction,
+            bucket: Mutex::new(Bucket {
+                tokens: 0.0,
+                last_refill: time::Instant::now(),
+            }),
+        }
+    }
+
+    /// Blocks until `bytes` may be transferred.
+    pub fn take(&self, bytes: usize) {
+        let rate = match self.config.limit(self.direction) {
+            Some(rate) => rate as f64,
+            None => return,
+        };
+
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = time::Instant::now();
+        let refill = now.duration_since(bucket.last_refill).as_secs_f64() * rate;
+        bucket.tokens = (bucket.tokens + refill).min(rate);
+        bucket.last_refill = now;
+
+        bucket.tokens -= bytes as f64;
+        if bucket.tokens < 0.0 {
+            thread::sleep(time::Duration::from_secs_f64(-bucket.tokens / rate));
+            bucket.tokens = 0.0;
+            bucket.last_refill = time::Instant::now();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time_of_day(time_of_day: &str) -> chrono::NaiveTime {
+        chrono::NaiveTime::parse_from_str(time_of_day, "%H:%M").unwrap()
+    }
+
+    fn window(from: &str, to: &str) -> ScheduleWindow {
+        ScheduleWindow {
+            from: time_of_day(from),
+            to: time_of_day(to),
+            upload_bytes_per_sec: None,
+            download_bytes_per_sec: Some(1024),
+        }
+    }
+
+    #[test]
+    fn parses_rates_with_units() {
+        assert_eq!(parse_rate("1048576").unwrap(), 1048576);
+        assert_eq!(parse_rate("500k").unwrap(), 500 * 1024);
+        assert_eq!(parse_rate(" 2M ").unwrap(), 2 * 1024 * 1024);
+        assert_eq!(parse_rate("1.5G").unwrap(), 3 * 512 * 1024 * 1024);
+        assert!(parse_rate("fast").is_err());
+        assert!(parse_rate("").is_err());
+    }
+
+    #[test]
+    fn schedule_windows_may_wrap_past_midnight() {
+        let day = window("09:00", "17:00");
+        assert!(day.contains(time_of_day("09:00")));
+        assert!(!day.contains(time_of_day("17:00")));
+
+        let night = window("22:00", "06:00");
+        assert!(night.contains(time_of_day("23:30")));
+        assert!(night.contains(time_of_day("05:59")));
+        assert!(!night.contains(time_of_day("12:00")));
+        assert_eq!(night.limit(Direction::Download), Some(1024));
+        assert_eq!(night.limit(Direction::Upload), None);
+    }
+
+    #[test]
+    fn override_applies_to_both_directions() {
+        let mut rate_limit_config = RateLimitConfig {
+            upload_bytes_per_sec: Some(10),
+            schedule: vec![window("00:00", "00:00")],
+            ..Default::default()
+        };
+        rate_limit_config.override_limit(2048);
+        assert_eq!(rate_limit_config.limit(Direction::Upload), Some(2048));
+        assert_eq!(rate_limit_config.limit(Direction::Download), Some(2048));
+
+        // `--limit-rate 0` lifts the limits.
+        rate_limit_config.override_limit(0);
+        assert_eq!(rate_limit_config.limit(Direction::Upload), None);
+    }
+}
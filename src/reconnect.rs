@@ -0,0 +1,92 @@
+use std::{thread, time};
+
+use rand::Rng;
+
+use crate::{config, errors, metrics};
+
+/// Exponential backoff with jitter: each delay is drawn from the upper half of a window that
+/// doubles per attempt, up to `max`.
+pub struct Backoff {
+    initial: time::Duration,
+    max: time::Duration,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(initial: time::Duration, max: time::Duration) -> Self {
+        Self {
+            initial,
+            max,
+            attempt: 0,
+        }
+    }
+
+    pub fn next_delay(&mut self) -> time::Duration {
+        let window = self
+            .initial
+            .saturating_mul(2u32.saturating_pow(self.attempt))
+            .min(self.max);
+        self.attempt += 1;
+        let half = window / 2;
+        half + half.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+    }
+}
+
+/// Runs `operation`, reconnecting with backoff while it fails on the connection. `operation` must
+/// pick up from the last change the server acknowledged, which both sync directions do because
+/// they persist progress after every change.
+pub(crate) fn with_reconnect<T>(
+    config: &config::ClientConfig,
+    mut operation: impl FnMut() -> Result<T, Box<dyn std::error::Error>>,
+) -> Result<T, Box<dyn std::error::Error>> {
+    let attempts = config.reconnect_attempts();
+    let mut backoff = Backoff::new(
+        config.reconnect_initial_delay(),
+        config.reconnect_max_delay(),
+    );
+    let mut attempt = 1;
+    loop {
+        match operation() {
+            Err(err) if attempt < attempts && errors::is_connection_error(&*err) => {
+                let delay = backoff.next_delay();
+                log::warn!(
+                    "Connection failed ({}), reconnecting in {:.1}s ({} of {})",
+                    err,
+                    delay.as_secs_f64(),
+                    attempt,
+                    attempts - 1
+                );
+                thread::sleep(delay);
+                metrics::record_reconnect();
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let mut backoff = Backoff::new(
+            time::Duration::from_millis(100),
+            time::Duration::from_secs(1),
+        );
+        for window_ms in [100, 200, 400, 800, 1000, 1000] {
+            let delay = backoff.next_delay();
+            assert!(delay >= time::Duration::from_millis(window_ms / 2));
+            assert!(delay <= time::Duration::from_millis(window_ms));
+        }
+    }
+
+    #[test]
+    fn backoff_does_not_overflow() {
+        let mut backoff = Backoff::new(time::Duration::from_secs(1), time::Duration::from_secs(30));
+        for _ in 0..100 {
+            assert!(backoff.next_delay() <= time::Duration::from_secs(30));
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use std::path;
+
+use hcs_lib::data;
+
+use crate::{
+    bytes_to_transmission_type, config, extra_data, open_connection, progress, rate_limit,
+    sync_server_to_client, transmission_type_to_bytes,
+};
+
+pub(crate) fn list_remote(
+    config: &config::ClientConfig,
+    remote_path: &str,
+) -> Result<Vec<extra_data::RemoteEntry>, Box<dyn std::error::Error>> {
+    let mut tcp_connection = open_connection(config)?;
+
+    let transmission = data::Transmission::ExtraData(extra_data::ExtraData::ListRemote {
+        path: remote_path.to_string(),
+    });
+    let bytes = transmission_type_to_bytes(transmission)?;
+    tcp_connection.write(&bytes)?;
+
+    let response = tcp_connection.read_next_chunk()?;
+    match bytes_to_transmission_type(&response)? {
+        data::Transmission::ExtraData(extra_data::ExtraData::RemoteListing(remote_entries)) => {
+            Ok(remote_entries)
+        }
+        data::Transmission::Error(err) => Err(format!("Server error: {:?}", err).into()),
+        _ => Err("Server did not respond with RemoteListing".into()),
+    }
+}
+
+pub(crate) fn fetch_file(
+    config: &config::ClientConfig,
+    remote_path: &str,
+    destination: &path::Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut tcp_connection = open_connection(config)?;
+
+    let transmission = data::Transmission::ExtraData(extra_data::ExtraData::FetchFile {
+        path: remote_path.to_string(),
+    });
+    let bytes = transmission_type_to_bytes(transmission)?;
+    tcp_connection.write(&bytes)?;
+
+    let response = tcp_connection.read_next_chunk()?;
+    let file_create = match bytes_to_transmission_type(&response)? {
+        data::Transmission::ChangeEvent(data::ChangeEvent::File(data::FileEvent::Create(
+            file_create,
+        ))) => file_create,
+        data::Transmission::Error(err) => return Err(format!("Server error: {:?}", err).into()),
+        _ => return Err("Server did not respond with file create".into()),
+    };
+
+    log::info!(
+        "Fetching `{}` ({} bytes) to `{}`",
+        remote_path,
+        file_create.size(),
+        destination.display()
+    );
+    let rate_limiter = rate_limit::RateLimiter::new(config, rate_limit::Direction::Download);
+    let progress = progress::Progress::new(
+        rate_limit::Direction::Download,
+        Some(1),
+        Some(file_create.size()),
+    );
+    let file_progress = progress.file(remote_path, file_create.size());
+    if !sync_server_to_client::receive_file(
+        &mut tcp_connection,
+        file_create.size(),
+        destination,
+        &rate_limiter,
+        &file_progress,
+    )? {
+        return Err(format!("Server skipped `{}`", remote_path).into());
+    }
+    drop(file_progress);
+    progress.change_done();
+    Ok(())
+}
+
+pub fn ls_remote(
+    config: &config::ClientConfig,
+    remote_path: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for remote_entry in list_remote(config, remote_path)? {
+        if remote_entry.is_directory {
+            println!("{:>12}  {}/", "-", remote_entry.path);
+        } else {
+            println!("{:>12}  {}", remote_entry.size, remote_entry.path);
+        }
+    }
+    Ok(())
+}
+
+/// Downloads a single file without touching the server version, the change queue or the storage
+/// directory.
+pub fn fetch(
+    config: &config::ClientConfig,
+    remote_path: &str,
+    destination: Option<&path::Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let destination = match destination {
+        Some(destination) if destination.is_dir() => {
+            destination.join(path::Path::new(remote_path).file_name().unwrap_or_default())
+        }
+        Some(destination) => destination.to_path_buf(),
+        None => path::PathBuf::from(path::Path::new(remote_path).file_name().unwrap_or_default()),
+    };
+
+    fetch_file(config, remote_path, &destination)
+}
@@ -0,0 +1,422 @@
+use std::{fs, path};
+
+use hcs_lib::{client_database, data};
+
+use crate::{changes, config, remote};
+
+#[derive(serde::Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SelectiveSyncConfig {
+    #[serde(default)]
+    include: Vec<path::PathBuf>,
+    #[serde(default)]
+    exclude: Vec<path::PathBuf>,
+}
+
+// Rules added with `hcs selective add|remove`, stored in `program_data_directory/selective_sync`.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default, PartialEq)]
+struct SelectiveSyncState {
+    rules: Vec<(path::PathBuf, bool)>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Selection {
+    Apply,
+    Skip,
+    RemoveLocal(path::PathBuf),
+    // Moved out of an excluded directory, so there is no local copy to move.
+    Download {
+        path: path::PathBuf,
+        is_directory: bool,
+    },
+}
+
+/// Decides which subtrees of the server are mirrored into `storage_directory`. The most specific
+/// rule wins, and rules added from the command line win over `Config.toml`.
+pub struct SelectiveSync {
+    state_path: path::PathBuf,
+    config_rules: Vec<(path::PathBuf, bool)>,
+    state: SelectiveSyncState,
+}
+
+impl SelectiveSync {
+    pub fn init(config: &config::ClientConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let selective_sync_config = config.selective_sync_config();
+        let config_rules = selective_sync_config
+            .exclude
+            .iter()
+            .map(|path| (path.clone(), false))
+            .chain(
+                selective_sync_config
+                    .include
+                    .iter()
+                    .map(|path| (path.clone(), true)),
+            )
+            .collect();
+
+        let state_path = config
+            .file_handler_config()
+            .program_data_directory
+            .join("selective_sync");
+        let state = if state_path.exists() {
+            bincode::deserialize(&fs::read(&state_path)?)?
+        } else {
+            SelectiveSyncState::default()
+        };
+
+        Ok(Self {
+            state_path,
+            config_rules,
+            state,
+        })
+    }
+
+    pub fn is_excluded(&self, relative_path: &path::Path) -> bool {
+        let mut selected: Option<&(path::PathBuf, bool)> = None;
+        for rule in self.config_rules.iter().chain(self.state.rules.iter()) {
+            if !relative_path.starts_with(&rule.0) {
+                continue;
+            }
+            match selected {
+                Some(selected_rule)
+                    if selected_rule.0.components().count() > rule.0.components().count() => {}
+                _ => selected = Some(rule),
+            }
+        }
+        matches!(selected, Some((_, false)))
+    }
+
+    pub fn select(&self, change_event: &data::ChangeEvent) -> Selection {
+        let (from_path, to_path, is_directory) = match change_event {
+            data::ChangeEvent::File(data::FileEvent::Move(file_move)) => {
+                (file_move.from_path(), file_move.to_path(), false)
+            }
+            data::ChangeEvent::Directory(data::DirectoryEvent::Move(directory_move)) => {
+                (directory_move.from_path(), directory_move.to_path(), true)
+            }
+            _ => {
+                let excluded = changes::change_event_paths(change_event)
+                    .iter()
+                    .any(|path| self.is_excluded(path));
+                return if excluded {
+                    Selection::Skip
+                } else {
+                    Selection::Apply
+                };
+            }
+        };
+
+        self.select_move(from_path, to_path, is_directory)
+    }
+
+    fn select_move(&self, from_path: &str, to_path: &str, is_directory: bool) -> Selection {
+        match (
+            self.is_excluded(path::Path::new(from_path)),
+            self.is_excluded(path::Path::new(to_path)),
+        ) {
+            (false, false) => Selection::Apply,
+            (true, true) => Selection::Skip,
+            (false, true) => Selection::RemoveLocal(from_path.into()),
+            (true, false) => Selection::Download {
+                path: to_path.into(),
+                is_directory,
+            },
+        }
+    }
+
+    fn set_rule(
+        &mut self,
+        relative_path: &path::Path,
+        included: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.state
+            .rules
+            .retain(|(path, _)| !path.starts_with(relative_path));
+        self.state
+            .rules
+            .push((relative_path.to_path_buf(), included));
+        fs::write(&self.state_path, bincode::serialize(&self.state)?)?;
+        Ok(())
+    }
+
+    /// Frees the local copy of everything excluded, whether by `Config.toml` or by
+    /// `hcs selective remove`. The server copy is left untouched, and an excluded path with
+    /// unsynced local changes within it is kept until they are synced.
+    pub fn free_excluded(
+        &self,
+        file_handler_config: &client_database::FileHandlerConfig,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let pending_changes = changes::PendingChanges::read(file_handler_config);
+        self.free_excluded_within(file_handler_config, &pending_changes, path::Path::new(""))
+    }
+
+    fn free_excluded_within(
+        &self,
+        file_handler_config: &client_database::FileHandlerConfig,
+        pending_changes: &changes::PendingChanges,
+        relative_directory: &path::Path,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let directory = file_handler_config
+            .storage_directory
+            .join(relative_directory);
+        for entry in fs::read_dir(&directory)? {
+            let entry = entry?;
+            let relative_path = relative_directory.join(entry.file_name());
+            if self.is_excluded(&relative_path) {
+                if pending_changes.contains_within(&relative_path) {
+                    log::warn!(
+                        "Keeping excluded `{}` until its local changes are synced",
+                        relative_path.display()
+                    );
+                    continue;
+                }
+                log::info!("Freeing excluded `{}`", relative_path.display());
+                remove_untracked(file_handler_config, &relative_path)?;
+            } else if entry.file_type()?.is_dir() && self.has_rule_below(&relative_path) {
+                self.free_excluded_within(file_handler_config, pending_changes, &relative_path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn has_rule_below(&self, relative_path: &path::Path) -> bool {
+        self.config_rules
+            .iter()
+            .chain(self.state.rules.iter())
+            .any(|(path, _)| path.starts_with(relative_path))
+    }
+
+    /// Deletes change files for excluded paths that have no local copy, so freed subtrees are
+    /// never synced up. Edits to a local copy that is not freed yet are kept and synced first.
+    pub fn discard_excluded_changes(
+        &self,
+        file_handler_config: &client_database::FileHandlerConfig,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut pending_changes = changes::PendingChanges::read(file_handler_config);
+        let excluded: Vec<path::PathBuf> = pending_changes
+            .paths()
+            .filter(|path| {
+                self.is_excluded(path) && !file_handler_config.storage_directory.join(path).exists()
+            })
+            .cloned()
+            .collect();
+        for path in excluded {
+            log::debug!("Discarding change to excluded `{}`", path.display());
+            pending_changes.discard(&path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Removes a file or directory from the storage and symlink directories together with its custom
+/// metadata, so that `detect` does not record the removal as a deletion.
+pub fn remove_untracked(
+    file_handler_config: &client_database::FileHandlerConfig,
+    relative_path: &path::Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file_paths = client_database::FilePaths::from_relative_path(
+        relative_path.to_path_buf(),
+        client_database::Type::File,
+        client_database::FileLocation::StorageDir,
+        None,
+        file_handler_config,
+    )?;
+
+    let mut pending_changes = changes::PendingChanges::read(file_handler_config);
+    pending_changes.discard_within(relative_path)?;
+
+    if file_paths.storage_dir_path().is_dir() {
+        fs::remove_dir_all(&file_paths.storage_dir_path())?;
+    } else if file_paths.storage_dir_path().exists() {
+        fs::remove_file(&file_paths.storage_dir_path())?;
+    }
+
+    if file_paths.custom_metadata_path().exists() {
+        fs::remove_file(&file_paths.custom_metadata_path())?;
+    }
+
+    if fs::read_link(&file_paths.symlink_dir_path()).is_ok() {
+        symlink::remove_symlink_file(&file_paths.symlink_dir_path())?;
+    } else if file_paths.symlink_dir_path().is_dir() {
+        fs::remove_dir_all(&file_paths.symlink_dir_path())?;
+    }
+
+    Ok(())
+}
+
+/// Downloads a file or directory into the storage directory as if it had been synced down.
+pub fn download(
+    config: &config::ClientConfig,
+    relative_path: &path::Path,
+    is_directory: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file_handler_config = config.file_handler_config();
+    let file_paths = client_database::FilePaths::from_relative_path(
+        relative_path.to_path_buf(),
+        client_database::Type::File,
+        client_database::FileLocation::StorageDir,
+        None,
+        file_handler_config,
+    )?;
+
+    if is_directory {
+        fs::create_dir_all(&file_paths.storage_dir_path())?;
+        fs::create_dir_all(&file_paths.symlink_dir_path())?;
+    } else {
+        remote::fetch_file(
+            config,
+            &relative_path.to_string_lossy(),
+            &file_paths.storage_dir_path(),
+        )?;
+        if fs::read_link(&file_paths.symlink_dir_path()).is_err() {
+            symlink::symlink_file(
+                &file_paths.storage_dir_path(),
+                &file_paths.symlink_dir_path(),
+            )?;
+        }
+    }
+
+    let last_modified =
+        client_database::CustomMetadata::last_modified_of_file(file_paths.storage_dir_path())?;
+    let custom_metadata = client_database::CustomMetadata::new(last_modified);
+    custom_metadata.write_to_file(&file_paths)?;
+
+    if is_directory {
+        for remote_entry in remote::list_remote(config, &relative_path.to_string_lossy())? {
+            download(
+                config,
+                path::Path::new(&remote_entry.path),
+                remote_entry.is_directory,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+pub fn list(config: &config::ClientConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let selective_sync = SelectiveSync::init(config)?;
+    for (path, included) in selective_sync
+        .config_rules
+        .iter()
+        .chain(selective_sync.state.rules.iter())
+    {
+        let rule = if *included { "include" } else { "exclude" };
+        println!("{}\t{}", rule, path.display());
+    }
+    Ok(())
+}
+
+/// Mirrors a previously excluded directory again, downloading its current contents.
+pub fn add(
+    config: &config::ClientConfig,
+    relative_path: &path::Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut selective_sync = SelectiveSync::init(config)?;
+    let was_excluded = selective_sync.is_excluded(relative_path);
+    selective_sync.set_rule(relative_path, true)?;
+
+    if was_excluded {
+        log::info!("Downloading `{}`", relative_path.display());
+        download(config, relative_path, true)?;
+    }
+    Ok(())
+}
+
+/// Stops mirroring a directory and frees its local copy. The server copy is left untouched, and
+/// a local copy with unsynced changes is only freed by a `detect` after they are synced.
+pub fn remove(
+    config: &config::ClientConfig,
+    relative_path: &path::Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut selective_sync = SelectiveSync::init(config)?;
+    selective_sync.set_rule(relative_path, false)?;
+    // Records local edits before freeing, so the ones not synced yet keep their local copy.
+    crate::detect(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selective_sync(rules: &[(&str, bool)], cli_rules: &[(&str, bool)]) -> SelectiveSync {
+        let to_rules = |rules: &[(&str, bool)]| {
+            rules
+                .iter()
+                .map(|(path, included)| (path::PathBuf::from(path), *included))
+                .collect()
+        };
+        SelectiveSync {
+            state_path: path::PathBuf::new(),
+            config_rules: to_rules(rules),
+            state: SelectiveSyncState {
+                rules: to_rules(cli_rules),
+            },
+        }
+    }
+
+    #[test]
+    fn most_specific_rule_wins() {
+        let selective_sync = selective_sync(&[("media", false), ("media/photos", true)], &[]);
+        assert!(selective_sync.is_excluded(path::Path::new("media/videos/a.mp4")));
+        assert!(!selective_sync.is_excluded(path::Path::new("media/photos/a.jpg")));
+        assert!(!selective_sync.is_excluded(path::Path::new("docs/a.txt")));
+    }
+
+    #[test]
+    fn command_line_rules_win_over_config() {
+        let selective_sync = selective_sync(&[("media", false)], &[("media", true)]);
+        assert!(!selective_sync.is_excluded(path::Path::new("media/a.mp4")));
+    }
+
+    #[test]
+    fn moves_across_an_exclusion() {
+        let selective_sync = selective_sync(&[("media", false)], &[]);
+        assert_eq!(
+            selective_sync.select_move("docs/a", "docs/b", false),
+            Selection::Apply
+        );
+        assert_eq!(
+            selective_sync.select_move("media/a", "media/b", false),
+            Selection::Skip
+        );
+        assert_eq!(
+            selective_sync.select_move("docs/a", "media/a", false),
+            Selection::RemoveLocal("docs/a".into())
+        );
+        assert_eq!(
+            selective_sync.select_move("media/album", "docs/album", true),
+            Selection::Download {
+                path: "docs/album".into(),
+                is_directory: true
+            }
+        );
+    }
+
+    #[test]
+    fn only_directories_above_a_rule_are_searched() {
+        let selective_sync = selective_sync(&[("media/videos", false)], &[]);
+        assert!(selective_sync.has_rule_below(path::Path::new("")));
+        assert!(selective_sync.has_rule_below(path::Path::new("media")));
+        assert!(!selective_sync.has_rule_below(path::Path::new("docs")));
+    }
+
+    #[test]
+    fn excluded_paths_with_unsynced_changes_are_kept() {
+        let directory = tempfile::tempdir().unwrap();
+        let file_handler_config = client_database::FileHandlerConfig {
+            storage_directory: directory.path().join("storage"),
+            symlink_directory: directory.path().join("symlink"),
+            temporary_directory: directory.path().join("temporary"),
+            program_data_directory: directory.path().join("program_data"),
+        };
+        let edited_file = file_handler_config.storage_directory.join("media/a.txt");
+        fs::create_dir_all(edited_file.parent().unwrap()).unwrap();
+        fs::write(&edited_file, "unsynced").unwrap();
+
+        let selective_sync = selective_sync(&[("media", false)], &[]);
+        let pending_changes = changes::PendingChanges::with_paths(&["media/a.txt"]);
+        selective_sync
+            .free_excluded_within(&file_handler_config, &pending_changes, path::Path::new(""))
+            .unwrap();
+        assert_eq!(fs::read_to_string(&edited_file).unwrap(), "unsynced");
+    }
+}
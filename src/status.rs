@@ -0,0 +1,143 @@
+use std::path;
+
+use chrono::TimeZone;
+use hcs_lib::{client_database, data};
+
+use crate::{
+    bytes_to_transmission_type, changes, config, control, extra_data, open_connection, pins,
+    transmission_type_to_bytes,
+};
+
+#[derive(Debug, serde::Serialize)]
+struct PendingChange {
+    kind: &'static str,
+    paths: Vec<path::PathBuf>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct Status {
+    server_version: i32,
+    remote_server_version: Option<i32>,
+    versions_behind: Option<i32>,
+    pending_changes: Vec<PendingChange>,
+    pinned: Vec<path::PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    live: Option<control::DaemonStatus>,
+}
+
+pub(crate) fn query_server_version(
+    config: &config::ClientConfig,
+) -> Result<i32, Box<dyn std::error::Error>> {
+    let mut tcp_connection = open_connection(config)?;
+
+    let transmission = data::Transmission::ExtraData(extra_data::ExtraData::ServerVersionQuery);
+    let bytes = transmission_type_to_bytes(transmission)?;
+    tcp_connection.write(&bytes)?;
+
+    let response = tcp_connection.read_next_chunk()?;
+    match bytes_to_transmission_type(&response)? {
+        data::Transmission::ServerVersion(sv) => Ok(sv.server_version()),
+        _ => Err("Server did not respond with ServerVersion".into()),
+    }
+}
+
+/// Prints the sync state without changing anything. Local edits not yet recorded by `detect` are
+/// not listed.
+pub fn status(
+    config: &config::ClientConfig,
+    json: bool,
+    live: Option<control::DaemonStatus>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file_handler_config = config.file_handler_config();
+    let server_version =
+        client_database::ServerVersion::init(&file_handler_config.program_data_directory)
+            .server_version();
+
+    // Only what `detect` already recorded. Detecting here would write change files.
+    let pending_changes: Vec<PendingChange> =
+        data::optimize_changes(client_database::read_changes(file_handler_config))
+            .into_iter()
+            .map(|(_, change_event)| PendingChange {
+                kind: changes::change_event_kind(&change_event),
+                paths: changes::change_event_paths(&change_event),
+            })
+            .collect();
+
+    let remote_server_version = match query_server_version(config) {
+        Ok(remote_server_version) => Some(remote_server_version),
+        Err(err) => {
+            log::warn!("Could not query the server version: {}", err);
+            None
+        }
+    };
+
+    let status = Status {
+        server_version,
+        remote_server_version,
+        versions_behind: remote_server_version
+            .map(|remote_server_version| (remote_server_version - server_version).max(0)),
+        pending_changes,
+        pinned: pins::pinned_paths(file_handler_config)?,
+        live,
+    };
+
+    if json {
+        println!("{}", serde_json::to_string(&status)?);
+        return Ok(());
+    }
+
+    match status.versions_behind {
+        Some(0) => println!("Server version: {} (up to date)", status.server_version),
+        Some(versions_behind) => println!(
+            "Server version: {} (server is {} versions ahead)",
+            status.server_version, versions_behind
+        ),
+        None => println!(
+            "Server version: {} (server unreachable)",
+            status.server_version
+        ),
+    }
+
+    if let Some(live) = &status.live {
+        let state = match (live.syncing, live.paused) {
+            (true, _) => "syncing",
+            (false, true) => "paused",
+            (false, false) => "idle",
+        };
+        let last_sync = live
+            .last_sync
+            .and_then(|last_sync| chrono::Local.timestamp_opt(last_sync, 0).single())
+            .map(|last_sync| last_sync.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_else(|| "never".to_string());
+        println!(
+            "hcs live (pid {}): {}, last synced {}",
+            live.pid, state, last_sync
+        );
+        if let Some(last_error) = &live.last_error {
+            println!("  Last sync failed: {}", last_error);
+        }
+    }
+
+    if status.pending_changes.is_empty() {
+        println!("No pending local changes.");
+    } else {
+        println!("{} pending local changes:", status.pending_changes.len());
+        for pending_change in &status.pending_changes {
+            let paths: Vec<String> = pending_change
+                .paths
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect();
+            println!("  {:<18}{}", pending_change.kind, paths.join(" -> "));
+        }
+    }
+
+    if !status.pinned.is_empty() {
+        println!("Pinned:");
+        for pinned in &status.pinned {
+            println!("  {}", pinned.display());
+        }
+    }
+
+    Ok(())
+}
@@ -1,23 +1,63 @@
-use std::{fs, net};
+use std::{collections::VecDeque, fs, path, time};
 
 use hcs_lib::{client_database, data, protocol};
 
 mod file_create;
 mod file_modify;
 
-use crate::{bytes_to_transmission_type, config, errors, extra_data, transmission_type_to_bytes};
+use crate::{
+    bytes_to_transmission_type, changes, config, detect, engine, errors, extra_data, history,
+    ignore_rules, merge, open_connection, pins, progress, rate_limit, reconnect,
+    sync_server_to_client, transmission_type_to_bytes,
+};
+
+// A change event together with the change file it was read from.
+type Change = (path::PathBuf, data::ChangeEvent);
 
 pub fn sync_client_to_server(
     config: &config::ClientConfig,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let server_version =
-        client_database::ServerVersion::init(&config.file_handler_config().program_data_directory);
+    let attempts = config.sync_up_attempts();
+    for attempt in 1..=attempts {
+        let mut server_version = client_database::ServerVersion::init(
+            &config.file_handler_config().program_data_directory,
+        );
+        let mut base_store =
+            merge::BaseStore::init(config.file_handler_config(), config.merge_globs())?;
+        let ignore_rules = ignore_rules::IgnoreRules::init(config.file_handler_config())?;
+        let rate_limiter = rate_limit::RateLimiter::new(config, rate_limit::Direction::Upload);
 
-    start_transmission(
-        net::TcpStream::connect(config.tcp_addr())?,
-        &config.file_handler_config(),
-        server_version,
-    )?;
+        // Acknowledged changes are removed as they arrive, so a reconnect resumes after them.
+        let result = reconnect::with_reconnect(config, || {
+            start_transmission(
+                config,
+                &mut server_version,
+                &mut base_store,
+                &ignore_rules,
+                &rate_limiter,
+            )
+        });
+        match result {
+            Ok(()) => break,
+            Err(err) if err.downcast_ref() == Some(&errors::ClientError::ServerAhead) => {
+                if attempt == attempts {
+                    log::error!("Server is still ahead after {} attempts", attempts);
+                    return Err(err);
+                }
+                // Pull the server changes (resolving conflicts with the pending local changes),
+                // then retry with whatever local changes remain.
+                log::info!(
+                    "Server is ahead, syncing server to client before retrying ({} of {})",
+                    attempt,
+                    attempts
+                );
+                sync_server_to_client::sync_server_to_client(config)?;
+                // Conflicts can turn pending changes back into local files to detect.
+                detect(config)?;
+            }
+            Err(err) => return Err(err),
+        }
+    }
 
     let old_changes = fs::read_dir(
         &config
@@ -32,41 +72,83 @@ pub fn sync_client_to_server(
     Ok(())
 }
 
-fn start_transmission(
-    tcp_stream: net::TcpStream,
+fn read_changes(
     file_handler_config: &client_database::FileHandlerConfig,
-    mut server_version: client_database::ServerVersion,
+    ignore_rules: &ignore_rules::IgnoreRules,
+) -> Result<Vec<Change>, Box<dyn std::error::Error>> {
+    let changes = client_database::read_changes(file_handler_config);
+    let optimized_changes = data::optimize_changes(changes);
+    let (ignored_changes, changes): (Vec<_>, Vec<_>) = optimized_changes
+        .into_iter()
+        .map(|(change_id, change_event)| {
+            (
+                changes::change_file_path(file_handler_config, change_id),
+                change_event,
+            )
+        })
+        .partition(|change| ignore_rules.is_ignored_event(&change.1));
+    for ignored_change in ignored_changes {
+        log::debug!("Not sending ignored change {}", ignored_change.0.display());
+        fs::remove_file(ignored_change.0)?;
+    }
+    Ok(changes)
+}
+
+fn start_transmission(
+    config: &config::ClientConfig,
+    server_version: &mut client_database::ServerVersion,
+    base_store: &mut merge::BaseStore,
+    ignore_rules: &ignore_rules::IgnoreRules,
+    rate_limiter: &rate_limit::RateLimiter,
 ) -> Result<(), Box<dyn std::error::Error>> {
     log::info!("Starting sync client to server transmission");
-    let mut tcp_connection = protocol::TcpConnection::new(tcp_stream);
 
-    {
-        log::debug!("Sending greeting");
-        // Send greeting to server.
-        let greeting = data::Greeting::new("HCS CLIENT".to_string());
-        let transmission = data::Transmission::Greeting(greeting);
-        let bytes = transmission_type_to_bytes(transmission)?;
-        tcp_connection.write(&bytes)?;
-    }
+    let changes = read_changes(config.file_handler_config(), ignore_rules)?;
+    log::debug!("{} changes to send", changes.len());
 
-    {
-        log::debug!("Waiting for server to respond with proceed");
-        // Ensure the server is ready to proceed.
-        let response = tcp_connection.read_next_chunk()?;
-        let transmission = bytes_to_transmission_type(&response)?;
-        if transmission != data::Transmission::Proceed {
-            log::error!("Server did not respond with proceed");
-            return Err("Server did not respond with proceed".into());
-        }
-    }
+    let total_bytes = changes
+        .iter()
+        .filter_map(|change| match &change.1 {
+            data::ChangeEvent::File(data::FileEvent::Create(file_create)) => {
+                Some(file_create.path())
+            }
+            data::ChangeEvent::File(data::FileEvent::Modify(file_modify)) => {
+                Some(file_modify.path())
+            }
+            _ => None,
+        })
+        .filter_map(|path| {
+            fs::metadata(config.file_handler_config().storage_directory.join(path)).ok()
+        })
+        .map(|metadata| metadata.len())
+        .sum();
+    let progress = progress::Progress::new(
+        rate_limit::Direction::Upload,
+        Some(changes.len()),
+        Some(total_bytes),
+    );
 
-    let changes = {
-        let changes = client_database::read_changes(file_handler_config);
-        let optimized_changes = data::optimize_changes(changes);
-        optimized_changes
-    };
+    send_changes(
+        config,
+        changes,
+        server_version,
+        base_store,
+        rate_limiter,
+        &progress,
+    )
+}
 
-    log::debug!("{} changes to send", changes.len());
+/// Sends all changes in one `SyncClientToServer` transaction, so the server applies them in
+/// order on top of a single server version.
+fn send_changes(
+    config: &config::ClientConfig,
+    changes: Vec<Change>,
+    server_version: &mut client_database::ServerVersion,
+    base_store: &mut merge::BaseStore,
+    rate_limiter: &rate_limit::RateLimiter,
+    progress: &progress::Progress,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut tcp_connection = open_connection(config)?;
 
     {
         log::debug!("Sending SyncClientToServer");
@@ -87,8 +169,8 @@ fn start_transmission(
         let transmission = bytes_to_transmission_type(&response)?;
         match transmission {
             data::Transmission::ServerVersion(_) => {
-                log::error!("Server responded with ServerVersion. You must first sync the server to the client.");
-                return Err("Server responded with ServerVersion. You must first sync the server to the client.".into());
+                log::warn!("{}", errors::ClientError::ServerAhead);
+                return Err(errors::ClientError::ServerAhead.into());
             }
             data::Transmission::Proceed => {}
             _ => {
@@ -98,90 +180,255 @@ fn start_transmission(
         }
     }
 
-    {
-        let changes_len = changes.len();
-        // Loop over `SyncClientToServer` num_changes()
-        for (change_num, change) in changes.into_iter().enumerate() {
-            log::info!("Sending change {} of {}", change_num + 1, changes_len);
-            let cloned_change = {
-                let update_change = match change.1 {
-                    data::ChangeEvent::File(data::FileEvent::Create(mut file_create)) => {
-                        let file_size = fs::metadata(
-                            file_handler_config
-                                .storage_directory
-                                .join(&file_create.path()),
-                        )?
-                        .len();
-                        file_create.set_size(file_size);
-                        data::ChangeEvent::File(data::FileEvent::Create(file_create))
-                    }
-                    data::ChangeEvent::File(data::FileEvent::Modify(mut file_modify)) => {
-                        let file_size = fs::metadata(
-                            file_handler_config
-                                .storage_directory
-                                .join(&file_modify.path()),
-                        )?
-                        .len();
-                        file_modify.set_size(file_size);
-                        data::ChangeEvent::File(data::FileEvent::Modify(file_modify))
-                    }
-                    _ => change.1,
-                };
-                let cloned_change = update_change.clone();
-                // Send the ChangeEvent as a Transmission to the server.
-                let transmission = data::Transmission::<
-                    errors::ServerTcpError,
-                    extra_data::ExtraData,
-                >::ChangeEvent(update_change);
-                let bytes = transmission_type_to_bytes(transmission)?;
-                tcp_connection.write(&bytes)?;
-                cloned_change
-            };
-
-            match cloned_change {
-                data::ChangeEvent::File(file_event) => match file_event {
-                    data::FileEvent::Create(file_create) => {
-                        file_create::handle_file_create(
-                            &mut tcp_connection,
-                            &file_handler_config,
-                            file_create,
-                        )?;
-                    }
-                    data::FileEvent::Modify(file_modify) => {
-                        file_modify::handle_file_modify(
-                            &mut tcp_connection,
-                            &file_handler_config,
-                            file_modify,
-                        )?;
-                    }
-                    _ => {}
-                },
-                _ => {}
+    let changes_len = changes.len();
+    let pipeline_window = config.pipeline_window();
+    // Pipelined changes carry their sequence number, so an acknowledgement can't be credited to
+    // the wrong change.
+    let sequenced = pipeline_window > 1;
+    // Changes sent but not acknowledged yet, oldest first. The server acknowledges the changes of
+    // a transaction in the order it receives them.
+    let mut in_flight: VecDeque<(usize, Change)> = VecDeque::with_capacity(pipeline_window);
+
+    // Loop over `SyncClientToServer` num_changes()
+    for (sequence, change) in changes.into_iter().enumerate() {
+        engine::check_cancelled()?;
+        log::debug!("Sending change {} of {}", sequence + 1, changes_len);
+        let started = time::Instant::now();
+        let change = send_change(
+            &mut tcp_connection,
+            config.file_handler_config(),
+            change,
+            sequenced.then_some(sequence as u64),
+            rate_limiter,
+            progress,
+        )?;
+        let event_kind = changes::change_event_kind(&change.1);
+        let paths = changes::change_event_display_paths(&change.1);
+        log::info!(
+            direction = "up",
+            event_kind,
+            path = paths,
+            size = changes::change_event_size(&change.1),
+            duration_ms = started.elapsed().as_millis() as u64;
+            "Sent {} `{}`", event_kind, paths
+        );
+        in_flight.push_back((sequence, change));
+
+        if in_flight.len() >= pipeline_window {
+            if let Some(acknowledged) = in_flight.pop_front() {
+                receive_acknowledgement(
+                    &mut tcp_connection,
+                    config.file_handler_config(),
+                    acknowledged,
+                    sequenced,
+                    server_version,
+                    base_store,
+                    progress,
+                )?;
             }
+        }
+    }
 
-            {
-                log::debug!("Waiting for server to respond with new version.");
-                // get new server version and delete change file.
-                let bytes = tcp_connection.read_next_chunk()?;
-                let transmission = bytes_to_transmission_type(&bytes)?;
-                let sv = match transmission {
-                    data::Transmission::ServerVersion(sv) => sv,
-                    _ => {
-                        log::error!("Server did not respond with ServerVersion");
-                        return Err("Server did not respond with ServerVersion".into());
-                    }
+    while let Some(acknowledged) = in_flight.pop_front() {
+        receive_acknowledgement(
+            &mut tcp_connection,
+            config.file_handler_config(),
+            acknowledged,
+            sequenced,
+            server_version,
+            base_store,
+            progress,
+        )?;
+    }
+
+    Ok(())
+}
+
+// Sends the change event and its file contents, wrapped in a `SequencedChange` if `sequence`
+// is set. Returns the change as it was sent.
+fn send_change(
+    tcp_connection: &mut Box<protocol::TcpConnection>,
+    file_handler_config: &client_database::FileHandlerConfig,
+    change: Change,
+    sequence: Option<u64>,
+    rate_limiter: &rate_limit::RateLimiter,
+    progress: &progress::Progress,
+) -> Result<Change, Box<dyn std::error::Error>> {
+    let cloned_change = {
+        let update_change = match change.1 {
+            data::ChangeEvent::File(data::FileEvent::Create(mut file_create)) => {
+                let file_size = fs::metadata(
+                    file_handler_config
+                        .storage_directory
+                        .join(&file_create.path()),
+                )?
+                .len();
+                file_create.set_size(file_size);
+                data::ChangeEvent::File(data::FileEvent::Create(file_create))
+            }
+            data::ChangeEvent::File(data::FileEvent::Modify(mut file_modify)) => {
+                let file_size = fs::metadata(
+                    file_handler_config
+                        .storage_directory
+                        .join(&file_modify.path()),
+                )?
+                .len();
+                file_modify.set_size(file_size);
+                data::ChangeEvent::File(data::FileEvent::Modify(file_modify))
+            }
+            _ => change.1,
+        };
+        let cloned_change = update_change.clone();
+        // Send the ChangeEvent as a Transmission to the server.
+        let transmission = match sequence {
+            Some(sequence) => {
+                data::Transmission::ExtraData(extra_data::ExtraData::SequencedChange {
+                    sequence,
+                    change_event: update_change,
+                })
+            }
+            None => {
+                data::Transmission::<errors::ServerTcpError, extra_data::ExtraData>::ChangeEvent(
+                    update_change,
+                )
+            }
+        };
+        let bytes = transmission_type_to_bytes(transmission)?;
+        tcp_connection.write(&bytes)?;
+        cloned_change
+    };
+
+    if let data::ChangeEvent::File(file_event) = cloned_change.clone() {
+        match file_event {
+            data::FileEvent::Create(file_create) => {
+                file_create::handle_file_create(
+                    tcp_connection,
+                    file_handler_config,
+                    file_create,
+                    rate_limiter,
+                    progress,
+                )?;
+            }
+            data::FileEvent::Modify(file_modify) => {
+                file_modify::handle_file_modify(
+                    tcp_connection,
+                    file_handler_config,
+                    file_modify,
+                    rate_limiter,
+                    progress,
+                )?;
+            }
+            _ => {}
+        }
+    }
+
+    Ok((change.0, cloned_change))
+}
+
+// The server version the server answered the change at `sequence` with.
+fn acknowledged_server_version(
+    transmission: data::Transmission<errors::ServerTcpError, extra_data::ExtraData>,
+    sequence: usize,
+    sequenced: bool,
+) -> Result<i32, Box<dyn std::error::Error>> {
+    match transmission {
+        data::Transmission::ServerVersion(sv) if !sequenced => Ok(sv.server_version()),
+        data::Transmission::ExtraData(extra_data::ExtraData::Acknowledgement {
+            sequence: acknowledged,
+            server_version,
+        }) if sequenced => {
+            if acknowledged != sequence as u64 {
+                let err = errors::ClientError::OutOfSequence {
+                    expected: sequence as u64,
+                    received: acknowledged,
                 };
-                server_version.set(sv.server_version());
-
-                // delete change file
-                let change_path = file_handler_config
-                    .program_data_directory
-                    .join("changes")
-                    .join(format!("{}.tmp", change.0));
-                fs::remove_file(change_path)?;
+                log::error!("{}", err);
+                return Err(err.into());
             }
+            Ok(server_version)
+        }
+        _ => {
+            log::error!("Server did not acknowledge change {}", sequence + 1);
+            Err(format!("Server did not acknowledge change {}", sequence + 1).into())
         }
     }
+}
+
+fn receive_acknowledgement(
+    tcp_connection: &mut Box<protocol::TcpConnection>,
+    file_handler_config: &client_database::FileHandlerConfig,
+    (sequence, change): (usize, Change),
+    sequenced: bool,
+    server_version: &mut client_database::ServerVersion,
+    base_store: &mut merge::BaseStore,
+    progress: &progress::Progress,
+) -> Result<(), Box<dyn std::error::Error>> {
+    log::debug!(
+        "Waiting for server to respond with new version for change {}.",
+        sequence + 1
+    );
+    // get new server version and delete change file.
+    let bytes = tcp_connection.read_next_chunk()?;
+    let transmission = bytes_to_transmission_type(&bytes)?;
+    let new_server_version = acknowledged_server_version(transmission, sequence, sequenced)?;
+    log::info!(
+        direction = "up",
+        event_kind = changes::change_event_kind(&change.1),
+        path = changes::change_event_display_paths(&change.1),
+        server_version = new_server_version;
+        "Server acknowledged change {} at version {}", sequence + 1, new_server_version
+    );
+    server_version.set(new_server_version);
+    base_store.update(&change.1)?;
+    pins::Pins::init(file_handler_config)?.update(&change.1)?;
+
+    let mut entry = history::HistoryEntry::new(rate_limit::Direction::Upload, &change.1, "sent");
+    entry.server_version = Some(new_server_version);
+    history::record(file_handler_config, &entry)?;
+
+    // delete change file
+    fs::remove_file(change.0)?;
+    progress.change_done();
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn acknowledgement(
+        sequence: u64,
+        server_version: i32,
+    ) -> data::Transmission<errors::ServerTcpError, extra_data::ExtraData> {
+        data::Transmission::ExtraData(extra_data::ExtraData::Acknowledgement {
+            sequence,
+            server_version,
+        })
+    }
+
+    #[test]
+    fn matching_acknowledgement_yields_the_server_version() {
+        assert_eq!(
+            acknowledged_server_version(acknowledgement(3, 42), 3, true).unwrap(),
+            42
+        );
+    }
+
+    #[test]
+    fn acknowledgement_for_another_change_fails() {
+        let err = acknowledged_server_version(acknowledgement(4, 42), 3, true).unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&errors::ClientError::OutOfSequence {
+                expected: 3,
+                received: 4
+            })
+        );
+    }
+
+    #[test]
+    fn acknowledgement_is_rejected_without_pipelining() {
+        assert!(acknowledged_server_version(acknowledgement(0, 42), 0, false).is_err());
+    }
+}
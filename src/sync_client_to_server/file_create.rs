@@ -2,10 +2,14 @@ use std::{fs, io::Read};
 
 use hcs_lib::{client_database, data, protocol};
 
+use crate::{progress, rate_limit};
+
 pub fn handle_file_create(
     tcp_connection: &mut Box<protocol::TcpConnection>,
     file_handler_config: &client_database::FileHandlerConfig,
     file_create: data::FileCreate,
+    rate_limiter: &rate_limit::RateLimiter,
+    progress: &progress::Progress,
 ) -> Result<(), Box<dyn std::error::Error>> {
     // Read the file buffer by buffer, write into tcp stream.
     let file_path = file_handler_config
@@ -13,12 +17,15 @@ pub fn handle_file_create(
         .join(file_create.path());
     let file_size = file_create.size();
 
+    let file_progress = progress.file(file_create.path(), file_size);
     let packets = protocol::calculate_num_packets(file_size);
     let mut file = fs::File::open(&file_path)?;
     let mut buffer = vec![0; protocol::BUFFER_SIZE];
     for _ in 0..packets {
         let bytes_read = file.read(&mut buffer)?;
+        rate_limiter.take(bytes_read);
         tcp_connection.write(&buffer[..bytes_read])?;
+        file_progress.advance(bytes_read);
     }
 
     Ok(())
@@ -1,8 +1,15 @@
-use std::net;
+use std::{
+    fs,
+    io::{self, Write},
+    path, time,
+};
 
 use hcs_lib::{client_database, data, protocol};
 
-use crate::{bytes_to_transmission_type, config, transmission_type_to_bytes};
+use crate::{
+    bytes_to_transmission_type, changes, config, conflicts, connect, endpoint, engine, history,
+    pins, progress, quota, rate_limit, reconnect, selective, transmission_type_to_bytes,
+};
 
 mod directory_create;
 mod directory_delete;
@@ -11,27 +18,160 @@ mod file_create;
 mod file_delete;
 mod file_modify;
 mod file_move;
+mod placeholder;
 
 pub fn sync_server_to_client(
     config: &config::ClientConfig,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let server_version =
-        client_database::ServerVersion::init(&config.file_handler_config().program_data_directory);
+    let mut conflict_handler = conflicts::ConflictHandler::new(config)?;
+    let selective_sync = selective::SelectiveSync::init(config)?;
+    let rate_limiter = rate_limit::RateLimiter::new(config, rate_limit::Direction::Download);
+    // Paths moved out of excluded directories or away from local changes, downloaded once the
+    // events are applied.
+    let mut downloads = Vec::new();
 
-    start_transmission(
-        net::TcpStream::connect(config.tcp_addr())?,
-        &config.file_handler_config(),
-        server_version,
-    )?;
+    // The server version is saved after every change, so a reconnect resumes from there.
+    reconnect::with_reconnect(config, || {
+        let server_version = client_database::ServerVersion::init(
+            &config.file_handler_config().program_data_directory,
+        );
+        start_transmission(
+            connect(config)?,
+            config,
+            server_version,
+            &mut conflict_handler,
+            &selective_sync,
+            &rate_limiter,
+            &mut downloads,
+        )
+    })?;
+
+    downloads.extend(conflict_handler.take_downloads());
+    for (relative_path, is_directory) in downloads {
+        log::info!(
+            "Downloading `{}`, moved on the server without a local copy to move",
+            relative_path.display()
+        );
+        selective::download(config, &relative_path, is_directory)?;
+    }
+
+    quota::enforce(config)?;
 
     Ok(())
 }
 
+/// Suffix of the file a download is written to before it replaces its destination.
+pub(crate) const PARTIAL_SUFFIX: &str = ".hcs-partial";
+
+fn partial_path(destination: &path::Path) -> path::PathBuf {
+    let file_name = destination
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    destination.with_file_name(format!(".{}{}", file_name, PARTIAL_SUFFIX))
+}
+
+/// Writes `size` bytes of file content sent by the server to `destination`. Returns `false` if
+/// the server skipped the file instead. The content goes to a partial file first, so
+/// `destination` is only replaced once all of it arrived.
+pub(crate) fn receive_file(
+    tcp_connection: &mut Box<protocol::TcpConnection>,
+    size: u64,
+    destination: &path::Path,
+    rate_limiter: &rate_limit::RateLimiter,
+    file_progress: &progress::FileProgress,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let partial_path = partial_path(destination);
+    let mut file = fs::File::create(&partial_path)?;
+    let received = receive_into(tcp_connection, size, &mut file, rate_limiter, file_progress);
+    drop(file);
+    match received {
+        Ok(true) => {
+            fs::rename(&partial_path, destination)?;
+            Ok(true)
+        }
+        received => {
+            let _ = fs::remove_file(&partial_path);
+            received
+        }
+    }
+}
+
+fn receive_into(
+    tcp_connection: &mut Box<protocol::TcpConnection>,
+    size: u64,
+    writer: &mut impl Write,
+    rate_limiter: &rate_limit::RateLimiter,
+    file_progress: &progress::FileProgress,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let packets = protocol::calculate_num_packets(size);
+    for _ in 0..packets {
+        let bytes = tcp_connection.read_next_chunk()?;
+        rate_limiter.take(bytes.len());
+
+        if let Ok(transmission) = bytes_to_transmission_type(&bytes) {
+            match transmission {
+                data::Transmission::SkipCurrent => {
+                    return Ok(false);
+                }
+                _ => {
+                    return Err("Expected no transmission, got something one".into());
+                }
+            }
+        }
+
+        writer.write_all(&bytes)?;
+        file_progress.advance(bytes.len());
+    }
+    Ok(true)
+}
+
+// Reads and drops the content of a file the server sends but the client does not want.
+fn skip_file_content(
+    tcp_connection: &mut Box<protocol::TcpConnection>,
+    change_event: &data::ChangeEvent,
+    rate_limiter: &rate_limit::RateLimiter,
+    progress: &progress::Progress,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (path, size) = match change_event {
+        data::ChangeEvent::File(data::FileEvent::Create(file_create)) => {
+            (file_create.path(), file_create.size())
+        }
+        data::ChangeEvent::File(data::FileEvent::Modify(file_modify)) => {
+            (file_modify.path(), file_modify.size())
+        }
+        _ => return Ok(()),
+    };
+    let file_progress = progress.file(path, size);
+    receive_into(
+        tcp_connection,
+        size,
+        &mut io::sink(),
+        rate_limiter,
+        &file_progress,
+    )?;
+    Ok(())
+}
+
 fn handle_server_to_client_change_event(
     tcp_connection: &mut Box<protocol::TcpConnection>,
     file_handler_config: &client_database::FileHandlerConfig,
+    cloud_only_downloads: bool,
     change_event: data::ChangeEvent,
+    rate_limiter: &rate_limit::RateLimiter,
+    progress: &progress::Progress,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    if placeholder::handle_placeholder_change_event(
+        tcp_connection,
+        file_handler_config,
+        cloud_only_downloads,
+        &change_event,
+        rate_limiter,
+        progress,
+    )? {
+        return Ok(());
+    }
+
     {
         // handle that change event
         match change_event {
@@ -41,6 +181,8 @@ fn handle_server_to_client_change_event(
                         tcp_connection,
                         file_handler_config,
                         file_create,
+                        rate_limiter,
+                        progress,
                     )?;
                 }
                 data::FileEvent::Modify(file_modify) => {
@@ -48,6 +190,8 @@ fn handle_server_to_client_change_event(
                         tcp_connection,
                         file_handler_config,
                         file_modify,
+                        rate_limiter,
+                        progress,
                     )?;
                 }
                 data::FileEvent::Delete(file_delete) => {
@@ -87,12 +231,17 @@ fn handle_server_to_client_change_event(
 }
 
 fn start_transmission(
-    tcp_stream: net::TcpStream,
-    file_handler_config: &client_database::FileHandlerConfig,
+    stream: Box<dyn endpoint::Stream>,
+    config: &config::ClientConfig,
     mut server_version: client_database::ServerVersion,
+    conflict_handler: &mut conflicts::ConflictHandler,
+    selective_sync: &selective::SelectiveSync,
+    rate_limiter: &rate_limit::RateLimiter,
+    downloads: &mut Vec<(path::PathBuf, bool)>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     log::info!("Starting sync server to client transmission");
-    let mut tcp_connection = protocol::TcpConnection::new(tcp_stream);
+    let file_handler_config = config.file_handler_config();
+    let mut tcp_connection = protocol::TcpConnection::new(stream);
 
     {
         log::debug!("Sending greeting");
@@ -123,19 +272,85 @@ fn start_transmission(
         tcp_connection.write(&bytes)?;
     }
 
+    // The server does not announce how many changes it will send.
+    let progress = progress::Progress::new(rate_limit::Direction::Download, None, None);
+    // The last handled change, recorded in the history once the server sends the version after it.
+    let mut handled: Option<history::HistoryEntry> = None;
+
     loop {
+        engine::check_cancelled()?;
         log::info!("Waiting for change event");
         {
             let bytes = tcp_connection.read_next_chunk()?;
             let transmission = bytes_to_transmission_type(&bytes)?;
-            dbg!(&transmission);
             match transmission {
                 data::Transmission::ChangeEvent(change_event) => {
-                    handle_server_to_client_change_event(
-                        &mut tcp_connection,
-                        file_handler_config,
-                        change_event.clone(),
-                    )?;
+                    let started = time::Instant::now();
+                    let event_kind = changes::change_event_kind(&change_event);
+                    let paths = changes::change_event_display_paths(&change_event);
+                    let size = changes::change_event_size(&change_event);
+                    log::debug!(
+                        direction = "down", event_kind, path = paths, size;
+                        "Received {} `{}`", event_kind, paths
+                    );
+
+                    let outcome = match selective_sync.select(&change_event) {
+                        selective::Selection::Skip => {
+                            log::info!("Skipped change event in an excluded directory.");
+                            skip_file_content(
+                                &mut tcp_connection,
+                                &change_event,
+                                rate_limiter,
+                                &progress,
+                            )?;
+                            "excluded"
+                        }
+                        selective::Selection::RemoveLocal(relative_path) => {
+                            log::info!(
+                                "`{}` was moved into an excluded directory.",
+                                relative_path.display()
+                            );
+                            selective::remove_untracked(file_handler_config, &relative_path)?;
+                            "removed locally"
+                        }
+                        selective::Selection::Download { path, is_directory } => {
+                            downloads.push((path, is_directory));
+                            "queued for download"
+                        }
+                        selective::Selection::Apply => {
+                            if conflict_handler.check(&change_event)? {
+                                handle_server_to_client_change_event(
+                                    &mut tcp_connection,
+                                    file_handler_config,
+                                    config.cloud_only_downloads(),
+                                    change_event.clone(),
+                                    rate_limiter,
+                                    &progress,
+                                )?;
+                                conflict_handler.applied(&change_event)?;
+                                pins::Pins::init(file_handler_config)?.update(&change_event)?;
+                                "applied"
+                            } else {
+                                log::info!("Skipped change event to keep local changes.");
+                                "kept local"
+                            }
+                        }
+                    };
+
+                    log::info!(
+                        direction = "down",
+                        event_kind,
+                        path = paths,
+                        size,
+                        outcome,
+                        duration_ms = started.elapsed().as_millis() as u64;
+                        "Handled {} `{}`: {}", event_kind, paths, outcome
+                    );
+                    handled = Some(history::HistoryEntry::new(
+                        rate_limit::Direction::Download,
+                        &change_event,
+                        outcome,
+                    ));
                 }
                 data::Transmission::SkipCurrent => {
                     log::info!("Server sent skip current event.");
@@ -163,22 +378,47 @@ fn start_transmission(
             match transmission {
                 data::Transmission::ServerVersion(server_version_response) => {
                     log::info!(
+                        direction = "down",
+                        server_version = server_version_response.server_version();
                         "Server version: {}",
                         server_version_response.server_version()
                     );
                     server_version.set(server_version_response.server_version());
+                    progress.change_done();
+                    if let Some(mut entry) = handled.take() {
+                        entry.server_version = Some(server_version_response.server_version());
+                        history::record(file_handler_config, &entry)?;
+                    }
                 }
                 data::Transmission::TransactionComplete => {
                     log::info!("Server sent transaction complete event.");
+                    if let Some(entry) = handled.take() {
+                        history::record(file_handler_config, &entry)?;
+                    }
                     return Ok(());
                 }
 
                 _ => {
-                    dbg!(transmission);
-                    log::error!("Server did not respond with server version");
+                    log::error!(
+                        "Server did not respond with server version, got {:?}",
+                        transmission
+                    );
                     return Err("Server did not respond with server version".into());
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_file_is_a_hidden_sibling() {
+        assert_eq!(
+            partial_path(path::Path::new("dir/report.pdf")),
+            path::Path::new("dir/.report.pdf.hcs-partial")
+        );
+    }
+}
@@ -1,13 +1,16 @@
-use std::{fs, io::Write, path};
+use std::{fs, path};
 
 use hcs_lib::{client_database, data, protocol};
 
-use crate::bytes_to_transmission_type;
+use super::receive_file;
+use crate::{progress, rate_limit};
 
 pub fn handle_file_create(
     tcp_connection: &mut Box<protocol::TcpConnection>,
     file_handler_config: &client_database::FileHandlerConfig,
     file_create: data::FileCreate,
+    rate_limiter: &rate_limit::RateLimiter,
+    progress: &progress::Progress,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let file_paths = client_database::FilePaths::from_relative_path(
         path::PathBuf::from(file_create.path()),
@@ -24,25 +27,15 @@ pub fn handle_file_create(
         //     .truncate(true)
         //     .open(&file_paths.storage_dir_path())?;
 
-        let mut file = fs::File::create(&file_paths.storage_dir_path())?;
-        file.set_len(0)?;
-        let packets = protocol::calculate_num_packets(file_create.size());
-        for _ in 0..packets {
-            let bytes = tcp_connection.read_next_chunk()?;
-
-            match bytes_to_transmission_type(&bytes) {
-                Ok(transmission) => match transmission {
-                    data::Transmission::SkipCurrent => {
-                        return Ok(());
-                    }
-                    _ => {
-                        return Err("Expected no transmission, got something one".into());
-                    }
-                },
-                Err(_) => {}
-            };
-
-            file.write(&bytes)?;
+        let file_progress = progress.file(file_create.path(), file_create.size());
+        if !receive_file(
+            tcp_connection,
+            file_create.size(),
+            &file_paths.storage_dir_path(),
+            rate_limiter,
+            &file_progress,
+        )? {
+            return Ok(());
         }
     }
 
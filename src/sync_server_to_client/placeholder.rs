@@ -0,0 +1,141 @@
+use std::{fs, path};
+
+use hcs_lib::{client_database, data, protocol};
+
+use super::skip_file_content;
+use crate::{metadata, pins, placeholders, progress, rate_limit};
+
+/// Applies a change event to a cloud-only file without downloading it. With
+/// `cloud_only_downloads`, new files become placeholders too. Returns `false` if the event does
+/// not concern a placeholder.
+pub fn handle_placeholder_change_event(
+    tcp_connection: &mut Box<protocol::TcpConnection>,
+    file_handler_config: &client_database::FileHandlerConfig,
+    cloud_only_downloads: bool,
+    change_event: &data::ChangeEvent,
+    rate_limiter: &rate_limit::RateLimiter,
+    progress: &progress::Progress,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    match change_event {
+        data::ChangeEvent::File(data::FileEvent::Create(file_create)) => {
+            let relative_path = path::Path::new(file_create.path());
+            if !cloud_only_downloads || pins::is_pinned(file_handler_config, relative_path) {
+                return Ok(false);
+            }
+            skip_file_content(tcp_connection, change_event, rate_limiter, progress)?;
+
+            // Leave a placeholder recording the size of the server copy
+            let file_paths = client_database::FilePaths::from_relative_path(
+                path::PathBuf::from(file_create.path()),
+                client_database::Type::File,
+                client_database::FileLocation::StorageDir,
+                None,
+                file_handler_config,
+            )?;
+            if file_paths.storage_dir_path().exists() {
+                fs::remove_file(&file_paths.storage_dir_path())?;
+            }
+            // Dated like the server copy, which is what hydrating it downloads.
+            let mut metadata = metadata::Metadata::new(file_create.last_modified());
+            metadata.set_placeholder_size(Some(file_create.size()));
+            metadata.write(&file_paths)?;
+
+            if fs::read_link(&file_paths.symlink_dir_path()).is_err() {
+                symlink::symlink_file(
+                    &file_paths.storage_dir_path(),
+                    &file_paths.symlink_dir_path(),
+                )?;
+            }
+        }
+        data::ChangeEvent::File(data::FileEvent::Modify(file_modify)) => {
+            // Pinned placeholders are downloaded instead
+            let relative_path = path::Path::new(file_modify.path());
+            if !placeholders::is_placeholder(file_handler_config, relative_path)
+                || pins::is_pinned(file_handler_config, relative_path)
+            {
+                return Ok(false);
+            }
+            skip_file_content(tcp_connection, change_event, rate_limiter, progress)?;
+
+            // Update the placeholder's size and last modified time
+            let file_paths = client_database::FilePaths::from_relative_path(
+                path::PathBuf::from(file_modify.path()),
+                client_database::Type::File,
+                client_database::FileLocation::StorageDir,
+                None,
+                file_handler_config,
+            )?;
+            let mut metadata = metadata::Metadata::read(&file_paths)?;
+            metadata.set_placeholder_size(Some(file_modify.size()));
+            metadata.set_last_modified(file_modify.last_modified());
+            metadata.write(&file_paths)?;
+        }
+        data::ChangeEvent::File(data::FileEvent::Delete(file_delete)) => {
+            if !placeholders::is_placeholder(
+                file_handler_config,
+                path::Path::new(file_delete.path()),
+            ) {
+                return Ok(false);
+            }
+            let file_paths = client_database::FilePaths::from_relative_path(
+                path::PathBuf::from(file_delete.path()),
+                client_database::Type::File,
+                client_database::FileLocation::StorageDir,
+                None,
+                file_handler_config,
+            )?;
+
+            {
+                // Delete custom metadata file
+                fs::remove_file(&file_paths.custom_metadata_path())?;
+            }
+
+            {
+                // Delete symlink
+                symlink::remove_symlink_file(&file_paths.symlink_dir_path())?;
+            }
+        }
+        data::ChangeEvent::File(data::FileEvent::Move(file_move)) => {
+            if !placeholders::is_placeholder(
+                file_handler_config,
+                path::Path::new(file_move.from_path()),
+            ) {
+                return Ok(false);
+            }
+            let from_file_paths = client_database::FilePaths::from_relative_path(
+                path::PathBuf::from(file_move.from_path()),
+                client_database::Type::File,
+                client_database::FileLocation::StorageDir,
+                None,
+                file_handler_config,
+            )?;
+            let to_file_paths = client_database::FilePaths::from_relative_path(
+                path::PathBuf::from(file_move.to_path()),
+                client_database::Type::File,
+                client_database::FileLocation::StorageDir,
+                None,
+                file_handler_config,
+            )?;
+
+            {
+                // Move custom metadata file
+                fs::rename(
+                    &from_file_paths.custom_metadata_path(),
+                    &to_file_paths.custom_metadata_path(),
+                )?;
+            }
+
+            {
+                // Delete symlink, then create a new one
+                symlink::remove_symlink_file(&from_file_paths.symlink_dir_path())?;
+                symlink::symlink_file(
+                    &to_file_paths.storage_dir_path(),
+                    &to_file_paths.symlink_dir_path(),
+                )?;
+            }
+        }
+        _ => return Ok(false),
+    }
+
+    Ok(true)
+}
@@ -0,0 +1,86 @@
+use std::{path, time};
+
+use hcs_lib::client_database;
+use notify::Watcher as _;
+use tokio::sync::mpsc;
+
+use crate::{engine::AsyncResult, sync_server_to_client};
+
+// How long the storage directory must stay quiet before a burst of writes counts as one change.
+const SETTLE: time::Duration = time::Duration::from_secs(2);
+
+// Custom metadata and partial downloads are written by syncs, not by the user.
+fn is_sync_bookkeeping(path: &path::Path) -> bool {
+    path.file_name()
+        .map(|file_name| file_name.to_string_lossy())
+        .is_some_and(|file_name| {
+            (file_name.starts_with('.') && file_name.ends_with(".sc"))
+                || file_name.ends_with(sync_server_to_client::PARTIAL_SUFFIX)
+        })
+}
+
+/// Watches the storage directory, so live mode syncs soon after a local change instead of on its
+/// next tick.
+pub(crate) struct StorageWatcher {
+    _watcher: notify::RecommendedWatcher,
+    changes: mpsc::Receiver<()>,
+}
+
+impl StorageWatcher {
+    pub(crate) fn start(
+        file_handler_config: &client_database::FileHandlerConfig,
+    ) -> AsyncResult<Self> {
+        // One queued notification is enough to trigger the next sync.
+        let (sender, changes) = mpsc::channel(1);
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+                Ok(event) if event.kind.is_access() => {}
+                Ok(event) if event.paths.iter().all(|path| is_sync_bookkeeping(path)) => {}
+                Ok(_) => {
+                    let _ = sender.try_send(());
+                }
+                Err(err) => log::warn!("Watching the storage directory failed: {}", err),
+            })?;
+        watcher.watch(
+            &file_handler_config.storage_directory,
+            notify::RecursiveMode::Recursive,
+        )?;
+        Ok(Self {
+            _watcher: watcher,
+            changes,
+        })
+    }
+
+    /// Waits for a local change, then for the storage directory to settle.
+    pub(crate) async fn changed(&mut self) {
+        if self.changes.recv().await.is_none() {
+            return std::future::pending().await;
+        }
+        while tokio::time::timeout(SETTLE, self.changes.recv())
+            .await
+            .is_ok_and(|change| change.is_some())
+        {}
+    }
+
+    /// Forgets the changes seen so far, such as the writes of the sync that just ran.
+    pub(crate) fn clear(&mut self) {
+        while self.changes.try_recv().is_ok() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sync_bookkeeping_is_not_a_local_change() {
+        assert!(is_sync_bookkeeping(path::Path::new(
+            "/storage/dir/.a.txt.sc"
+        )));
+        assert!(is_sync_bookkeeping(path::Path::new(
+            "/storage/.a.txt.hcs-partial"
+        )));
+        assert!(!is_sync_bookkeeping(path::Path::new("/storage/a.txt")));
+        assert!(!is_sync_bookkeeping(path::Path::new("/storage/.hcsignore")));
+    }
+}